@@ -0,0 +1,10 @@
+-- Databases created before migrations were embedded already have the table.
+CREATE TABLE IF NOT EXISTS invoice (
+    address CHAR(42) PRIMARY KEY,
+    receiver CHAR(42) NOT NULL,
+    mnemonic VARCHAR NOT NULL,
+    state INTEGER NOT NULL,
+    value DOUBLE PRECISION NOT NULL,
+    lifetime INTEGER NOT NULL,
+    complete_action INTEGER NOT NULL
+);
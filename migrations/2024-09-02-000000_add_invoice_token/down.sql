@@ -0,0 +1,3 @@
+ALTER TABLE invoice
+    DROP COLUMN token,
+    DROP COLUMN decimals;
@@ -0,0 +1,3 @@
+ALTER TABLE invoice
+    ADD COLUMN token CHAR(42),
+    ADD COLUMN decimals INTEGER NOT NULL DEFAULT 18;
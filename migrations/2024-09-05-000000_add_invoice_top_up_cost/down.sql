@@ -0,0 +1 @@
+ALTER TABLE invoice DROP COLUMN top_up_cost;
@@ -0,0 +1 @@
+ALTER TABLE invoice ADD COLUMN top_up_cost DOUBLE PRECISION NOT NULL DEFAULT 0;
@@ -0,0 +1,5 @@
+ALTER TABLE invoice
+    DROP COLUMN derivation_index,
+    ALTER COLUMN mnemonic SET NOT NULL;
+
+DROP SEQUENCE invoice_derivation_index_seq;
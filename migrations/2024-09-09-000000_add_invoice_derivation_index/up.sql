@@ -0,0 +1,5 @@
+CREATE SEQUENCE invoice_derivation_index_seq AS INTEGER MINVALUE 0 START 0;
+
+ALTER TABLE invoice
+    ALTER COLUMN mnemonic DROP NOT NULL,
+    ADD COLUMN derivation_index INTEGER UNIQUE;
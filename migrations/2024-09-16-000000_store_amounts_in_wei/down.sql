@@ -0,0 +1,7 @@
+ALTER TABLE invoice
+    ALTER COLUMN value TYPE DOUBLE PRECISION
+        USING (value / power(10::NUMERIC, decimals))::DOUBLE PRECISION,
+    ALTER COLUMN top_up_cost DROP DEFAULT,
+    ALTER COLUMN top_up_cost TYPE DOUBLE PRECISION
+        USING (top_up_cost / power(10::NUMERIC, 18))::DOUBLE PRECISION,
+    ALTER COLUMN top_up_cost SET DEFAULT 0;
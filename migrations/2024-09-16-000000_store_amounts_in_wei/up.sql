@@ -0,0 +1,7 @@
+ALTER TABLE invoice
+    ALTER COLUMN value TYPE NUMERIC(78, 0)
+        USING round(value::NUMERIC * power(10::NUMERIC, decimals)),
+    ALTER COLUMN top_up_cost DROP DEFAULT,
+    ALTER COLUMN top_up_cost TYPE NUMERIC(78, 0)
+        USING round(top_up_cost::NUMERIC * power(10::NUMERIC, 18)),
+    ALTER COLUMN top_up_cost SET DEFAULT 0;
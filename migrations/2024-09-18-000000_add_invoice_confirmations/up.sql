@@ -0,0 +1 @@
+ALTER TABLE invoice ADD COLUMN confirmations INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+ALTER TABLE invoice DROP COLUMN scanned_block;
+
+DROP TABLE payments;
@@ -0,0 +1,14 @@
+CREATE TABLE payments (
+    id SERIAL PRIMARY KEY,
+    invoice_address CHAR(42) NOT NULL REFERENCES invoice (address),
+    tx_hash CHAR(66) NOT NULL,
+    block_number BIGINT NOT NULL,
+    sender CHAR(42) NOT NULL,
+    amount NUMERIC(78, 0) NOT NULL,
+    log_index INTEGER
+);
+
+CREATE UNIQUE INDEX payments_unique_transfer
+    ON payments (invoice_address, tx_hash, COALESCE(log_index, -1));
+
+ALTER TABLE invoice ADD COLUMN scanned_block BIGINT NOT NULL DEFAULT 0;
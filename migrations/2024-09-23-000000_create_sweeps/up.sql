@@ -0,0 +1,13 @@
+CREATE TABLE sweeps (
+    id SERIAL PRIMARY KEY,
+    invoice_address CHAR(42) NOT NULL UNIQUE REFERENCES invoice (address),
+    tx_hash CHAR(66),
+    nonce BIGINT,
+    max_fee_per_gas NUMERIC(78, 0),
+    max_priority_fee_per_gas NUMERIC(78, 0),
+    status INTEGER NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error VARCHAR,
+    next_attempt_at BIGINT NOT NULL DEFAULT 0,
+    updated_at BIGINT NOT NULL
+);
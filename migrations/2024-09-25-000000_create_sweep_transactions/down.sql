@@ -0,0 +1,4 @@
+ALTER TABLE sweeps DROP COLUMN cancel_requested;
+ALTER TABLE sweeps DROP COLUMN submitted_at;
+
+DROP TABLE sweep_transactions;
@@ -0,0 +1,21 @@
+CREATE TABLE sweep_transactions (
+    id SERIAL PRIMARY KEY,
+    invoice_address CHAR(42) NOT NULL REFERENCES invoice (address),
+    tx_hash CHAR(66) NOT NULL UNIQUE,
+    nonce BIGINT NOT NULL,
+    max_fee_per_gas NUMERIC(78, 0) NOT NULL,
+    max_priority_fee_per_gas NUMERIC(78, 0) NOT NULL,
+    kind INTEGER NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+INSERT INTO sweep_transactions
+    (invoice_address, tx_hash, nonce, max_fee_per_gas, max_priority_fee_per_gas, kind, created_at)
+SELECT invoice_address, tx_hash, nonce, max_fee_per_gas, max_priority_fee_per_gas, 0, updated_at
+FROM sweeps
+WHERE tx_hash IS NOT NULL;
+
+ALTER TABLE sweeps ADD COLUMN submitted_at BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE sweeps ADD COLUMN cancel_requested BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE sweeps SET submitted_at = updated_at WHERE tx_hash IS NOT NULL;
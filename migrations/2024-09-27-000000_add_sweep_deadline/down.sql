@@ -0,0 +1,2 @@
+ALTER TABLE sweeps DROP COLUMN alerted;
+ALTER TABLE sweeps DROP COLUMN deadline;
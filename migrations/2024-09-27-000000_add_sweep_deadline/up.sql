@@ -0,0 +1,2 @@
+ALTER TABLE sweeps ADD COLUMN deadline BIGINT;
+ALTER TABLE sweeps ADD COLUMN alerted BOOLEAN NOT NULL DEFAULT FALSE;
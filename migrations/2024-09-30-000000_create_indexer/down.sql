@@ -0,0 +1,4 @@
+DROP INDEX payments_block_number;
+
+DROP TABLE indexed_blocks;
+DROP TABLE indexer_cursor;
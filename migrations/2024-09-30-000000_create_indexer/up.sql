@@ -0,0 +1,11 @@
+CREATE TABLE indexer_cursor (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    last_scanned_block BIGINT NOT NULL
+);
+
+CREATE TABLE indexed_blocks (
+    number BIGINT PRIMARY KEY,
+    hash CHAR(66) NOT NULL
+);
+
+CREATE INDEX payments_block_number ON payments (block_number);
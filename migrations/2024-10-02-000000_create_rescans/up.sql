@@ -0,0 +1,11 @@
+CREATE TABLE rescans (
+    id SERIAL PRIMARY KEY,
+    from_block BIGINT NOT NULL,
+    to_block BIGINT NOT NULL,
+    next_block BIGINT NOT NULL,
+    status INTEGER NOT NULL,
+    missed_payments INTEGER NOT NULL DEFAULT 0,
+    repaired_invoices INTEGER NOT NULL DEFAULT 0,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
@@ -0,0 +1,2 @@
+ALTER TABLE invoice DROP COLUMN pending_amount;
+ALTER TABLE invoice DROP COLUMN pending_tx_hash;
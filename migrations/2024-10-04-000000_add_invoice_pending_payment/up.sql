@@ -0,0 +1,2 @@
+ALTER TABLE invoice ADD COLUMN pending_tx_hash CHAR(66);
+ALTER TABLE invoice ADD COLUMN pending_amount NUMERIC(78, 0);
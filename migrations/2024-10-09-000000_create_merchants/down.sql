@@ -0,0 +1,7 @@
+ALTER TABLE api_keys DROP COLUMN merchant_id;
+
+DROP INDEX invoice_merchant_id_idx;
+ALTER TABLE invoice DROP COLUMN webhook_url;
+ALTER TABLE invoice DROP COLUMN merchant_id;
+
+DROP TABLE merchants;
@@ -0,0 +1,15 @@
+CREATE TABLE merchants (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    receiver CHAR(42),
+    lifetime INTEGER,
+    complete_action INTEGER,
+    webhook_url VARCHAR,
+    created_at BIGINT NOT NULL
+);
+
+ALTER TABLE invoice ADD COLUMN merchant_id INTEGER REFERENCES merchants (id);
+ALTER TABLE invoice ADD COLUMN webhook_url VARCHAR;
+CREATE INDEX invoice_merchant_id_idx ON invoice (merchant_id);
+
+ALTER TABLE api_keys ADD COLUMN merchant_id INTEGER REFERENCES merchants (id);
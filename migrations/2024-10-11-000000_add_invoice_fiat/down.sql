@@ -0,0 +1,4 @@
+ALTER TABLE invoice DROP COLUMN rate_timestamp;
+ALTER TABLE invoice DROP COLUMN exchange_rate;
+ALTER TABLE invoice DROP COLUMN fiat_currency;
+ALTER TABLE invoice DROP COLUMN fiat_amount;
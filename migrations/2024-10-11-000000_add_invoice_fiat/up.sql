@@ -0,0 +1,4 @@
+ALTER TABLE invoice ADD COLUMN fiat_amount NUMERIC;
+ALTER TABLE invoice ADD COLUMN fiat_currency VARCHAR;
+ALTER TABLE invoice ADD COLUMN exchange_rate NUMERIC;
+ALTER TABLE invoice ADD COLUMN rate_timestamp BIGINT;
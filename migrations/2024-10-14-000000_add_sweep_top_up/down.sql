@@ -0,0 +1 @@
+ALTER TABLE sweeps DROP COLUMN top_up_tx_hash;
@@ -0,0 +1 @@
+ALTER TABLE sweeps ADD COLUMN top_up_tx_hash CHAR(66);
@@ -0,0 +1,136 @@
+use eyre::{eyre, Result};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+const KEY_PREFIX: &str = "pmk_";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Scope {
+    InvoicesCreate,
+    InvoicesRead,
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvoicesCreate => "invoices:create",
+            Self::InvoicesRead => "invoices:read",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = eyre::Report;
+
+    fn from_str(scope: &str) -> Result<Self> {
+        match scope.trim() {
+            "invoices:create" => Ok(Self::InvoicesCreate),
+            "invoices:read" => Ok(Self::InvoicesRead),
+            "admin" => Ok(Self::Admin),
+            _ => Err(eyre!(
+                "Unknown scope {scope}, expected invoices:create, invoices:read or admin"
+            )),
+        }
+    }
+}
+
+/// API key as stored, without its hash. The key itself is only shown once
+/// when it is issued.
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: u64,
+    pub revoked_at: Option<u64>,
+    /// Invoices of this merchant only. Admin keys belong to no merchant.
+    pub merchant_id: Option<i32>,
+}
+
+/// Random key with 256 bits of entropy.
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{KEY_PREFIX}{}", hex::encode(bytes))
+}
+
+/// Keys are looked up by their SHA-256 hash. They are random, so a slow
+/// password hash would add nothing.
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+pub fn parse_scopes(scopes: &[String]) -> Result<Vec<Scope>> {
+    if scopes.is_empty() {
+        return Err(eyre!("At least one scope is required"));
+    }
+    scopes.iter().map(|scope| scope.parse()).collect()
+}
+
+/// Admin keys manage every merchant, so they may not belong to one. All other
+/// keys must.
+pub fn check_merchant(scopes: &[Scope], merchant_id: Option<i32>) -> Result<()> {
+    match (scopes.contains(&Scope::Admin), merchant_id) {
+        (true, Some(_)) => Err(eyre!("Keys with admin scope can not belong to a merchant")),
+        (false, None) => Err(eyre!("Keys without admin scope must belong to a merchant")),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scopes(scopes: &[&str]) -> Vec<String> {
+        scopes.iter().map(|scope| scope.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_scopes() {
+        assert_eq!(
+            parse_scopes(&scopes(&["invoices:create", " invoices:read", "admin"])).unwrap(),
+            vec![Scope::InvoicesCreate, Scope::InvoicesRead, Scope::Admin]
+        );
+        for scope in [Scope::InvoicesCreate, Scope::InvoicesRead, Scope::Admin] {
+            assert_eq!(scope.as_str().parse::<Scope>().unwrap(), scope);
+        }
+    }
+
+    #[test]
+    fn rejects_missing_and_unknown_scopes() {
+        assert!(parse_scopes(&[]).is_err());
+        assert!(parse_scopes(&scopes(&["invoices:read", "invoices:delete"])).is_err());
+        assert!(parse_scopes(&scopes(&["Admin"])).is_err());
+    }
+
+    #[test]
+    fn admin_keys_belong_to_no_merchant() {
+        assert!(check_merchant(&[Scope::Admin], None).is_ok());
+        assert!(check_merchant(&[Scope::InvoicesRead, Scope::Admin], None).is_ok());
+        assert!(check_merchant(&[Scope::Admin], Some(1)).is_err());
+    }
+
+    #[test]
+    fn other_keys_belong_to_a_merchant() {
+        assert!(check_merchant(&[Scope::InvoicesCreate], Some(1)).is_ok());
+        assert!(check_merchant(&[Scope::InvoicesCreate, Scope::InvoicesRead], None).is_err());
+    }
+
+    #[test]
+    fn generated_keys_are_unique_and_hashed() {
+        let key = generate_key();
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_ne!(key, generate_key());
+        assert_eq!(hash_key(&key), hash_key(&key));
+        assert_eq!(hash_key(&key).len(), 64);
+    }
+}
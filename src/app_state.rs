@@ -1,3 +1,4 @@
+use crate::invoice_service::InvoiceService;
 use crate::invoices::InvoiceManager;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -5,4 +6,7 @@ use tokio::sync::Mutex;
 #[derive(Clone)]
 pub struct AppState {
     pub invoice_manager: Arc<Mutex<InvoiceManager>>,
+    /// Serves read endpoints without waiting for the manager.
+    pub invoice_service: InvoiceService,
+    pub admin_token: Option<String>,
 }
@@ -0,0 +1,145 @@
+use crate::api_keys::{hash_key, Scope};
+use crate::app_state::AppState;
+use crate::controller::RouteError;
+use crate::merchants::MerchantScope;
+use crate::utils::constant_time_eq;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest};
+use futures_util::future::{ready, Ready};
+use log::info;
+
+/// Authenticated caller of a request, stored in the request extensions.
+#[derive(Clone)]
+pub struct Caller {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub merchant: MerchantScope,
+}
+
+impl Caller {
+    /// `admin` grants every other scope.
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+}
+
+/// Handlers behind `require_*` take the caller as an argument.
+impl FromRequest for Caller {
+    type Error = RouteError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            request
+                .extensions()
+                .get::<Caller>()
+                .cloned()
+                .ok_or(RouteError::Unauthorized),
+        )
+    }
+}
+
+pub async fn require_invoices_read(
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    require(Scope::InvoicesRead, request, next).await
+}
+
+pub async fn require_invoices_create(
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    require(Scope::InvoicesCreate, request, next).await
+}
+
+pub async fn require_admin(
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    require(Scope::Admin, request, next).await
+}
+
+/// Lets the request through when its bearer token is an active API key with
+/// `scope`. Denials are written to the audit log.
+async fn require(
+    scope: Scope,
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let ctx = request
+        .app_data::<web::Data<AppState>>()
+        .expect("AppState is registered")
+        .clone();
+    let target = format!("{} {}", request.method(), request.path());
+    let peer = request
+        .peer_addr()
+        .map(|peer| peer.to_string())
+        .unwrap_or_default();
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .map(str::to_string);
+
+    let caller = match token {
+        Some(token) => authenticate(&ctx, token).await?,
+        None => None,
+    };
+    match caller {
+        Some(caller) if caller.has_scope(scope) => {
+            request.extensions_mut().insert(caller);
+            next.call(request).await
+        }
+        Some(caller) => {
+            info!(target: "audit", "{target} by {} from {peer} denied: missing scope {scope}", caller.name);
+            Err(RouteError::Forbidden.into())
+        }
+        None => {
+            info!(target: "audit", "{target} from {peer} denied: unauthorized");
+            Err(RouteError::Unauthorized.into())
+        }
+    }
+}
+
+/// ADMIN_TOKEN is accepted as a key with the `admin` scope, so that the first
+/// keys can be issued over the API.
+async fn authenticate(ctx: &AppState, token: String) -> Result<Option<Caller>, RouteError> {
+    if ctx
+        .admin_token
+        .as_ref()
+        .is_some_and(|admin_token| constant_time_eq(token.as_bytes(), admin_token.as_bytes()))
+    {
+        return Ok(Some(Caller {
+            name: "ADMIN_TOKEN".to_string(),
+            scopes: vec![Scope::Admin],
+            merchant: MerchantScope::All,
+        }));
+    }
+
+    let invoice_service = ctx.invoice_service.clone();
+    let api_key = web::block(move || invoice_service.find_api_key(&hash_key(&token))).await??;
+    Ok(api_key.map(|api_key| Caller {
+        name: format!("key {} ({})", api_key.id, api_key.name),
+        scopes: api_key.scopes,
+        merchant: api_key
+            .merchant_id
+            .map_or(MerchantScope::All, MerchantScope::Merchant),
+    }))
+}
+
+/// Caller and peer address of an authenticated request, for audit logs.
+pub fn describe_caller(request: &HttpRequest) -> String {
+    let peer = request
+        .peer_addr()
+        .map(|peer| peer.to_string())
+        .unwrap_or_default();
+    match request.extensions().get::<Caller>() {
+        Some(caller) => format!("{} from {peer}", caller.name),
+        None => peer,
+    }
+}
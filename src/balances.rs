@@ -0,0 +1,81 @@
+use crate::erc20::IERC20;
+use crate::invoices::ProviderArc;
+use alloy::eips::BlockId;
+use alloy::network::TransactionBuilder;
+use alloy::primitives::{Address, Bytes, U256};
+use alloy::providers::Provider;
+use alloy::rpc::client::{BatchRequest, Waiter};
+use alloy::rpc::types::TransactionRequest;
+use alloy::sol_types::SolCall;
+use eyre::Result;
+use futures_util::{stream, StreamExt, TryStreamExt};
+
+/// Balance lookups sent in one JSON-RPC batch.
+const BATCH_SIZE: usize = 100;
+/// Batches in flight at the same time.
+const MAX_CONCURRENT_BATCHES: usize = 4;
+
+/// ETH balance of `address`, or its `token` balance, at `block`.
+pub struct BalanceQuery {
+    pub address: Address,
+    pub token: Option<Address>,
+    pub block: u64,
+}
+
+enum BalanceWaiter {
+    Native(Waiter<U256>),
+    Token(Waiter<Bytes>),
+}
+
+/// Looks up the balances in JSON-RPC batches of `BATCH_SIZE`, at most
+/// `MAX_CONCURRENT_BATCHES` at a time. Balances are returned in query order.
+pub async fn fetch_balances(
+    provider_arc: ProviderArc,
+    queries: &[BalanceQuery],
+) -> Result<Vec<U256>> {
+    let batches = queries
+        .chunks(BATCH_SIZE)
+        .map(|queries| fetch_batch(provider_arc.clone(), queries))
+        .collect::<Vec<_>>();
+    let batches: Vec<Vec<U256>> = stream::iter(batches)
+        .buffered(MAX_CONCURRENT_BATCHES)
+        .try_collect()
+        .await?;
+    Ok(batches.into_iter().flatten().collect())
+}
+
+async fn fetch_batch(provider_arc: ProviderArc, queries: &[BalanceQuery]) -> Result<Vec<U256>> {
+    let mut batch = BatchRequest::new(provider_arc.client());
+    let waiters = queries
+        .iter()
+        .map(|query| {
+            let block = BlockId::number(query.block);
+            Ok(match query.token {
+                Some(token) => {
+                    let request = TransactionRequest::default().with_to(token).with_input(
+                        IERC20::balanceOfCall {
+                            account: query.address,
+                        }
+                        .abi_encode(),
+                    );
+                    BalanceWaiter::Token(batch.add_call("eth_call", &(request, block))?)
+                }
+                None => BalanceWaiter::Native(
+                    batch.add_call("eth_getBalance", &(query.address, block))?,
+                ),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    batch.send().await?;
+
+    let mut balances = Vec::with_capacity(waiters.len());
+    for waiter in waiters {
+        balances.push(match waiter {
+            BalanceWaiter::Native(waiter) => waiter.await?,
+            BalanceWaiter::Token(waiter) => {
+                IERC20::balanceOfCall::abi_decode_returns(&waiter.await?, true)?._0
+            }
+        });
+    }
+    Ok(balances)
+}
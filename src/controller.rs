@@ -1,93 +1,529 @@
+use crate::api_keys::{check_merchant, generate_key, hash_key, parse_scopes};
 use crate::app_state::AppState;
+use crate::auth::{describe_caller, Caller};
+use crate::dto::{
+    ApiKeyResponse, InvoiceDetailsResponse, InvoiceResponse, IssuedApiKeyResponse,
+    MerchantResponse, PaymentResponse, RescanResponse, SweepResponse,
+};
+use crate::invoices::{InvoiceAction, InvoiceManager, InvoiceRequest, InvoiceState, InvoiceValue};
+use crate::merchants::{MerchantDefaults, MerchantScope};
+use crate::metrics;
+use crate::prices::PriceSourceUnavailable;
+use crate::utils::InvalidRequest;
+use actix_web::error::BlockingError;
+use actix_web::http::header::ContentType;
 use actix_web::http::StatusCode;
-use actix_web::{web, HttpResponse, Responder, ResponseError};
+use actix_web::{web, HttpRequest, HttpResponse, Responder, ResponseError};
+use alloy::primitives::Address;
+use alloy::transports::{RpcError, TransportError};
+use log::info;
 use serde::Deserialize;
 
 #[derive(thiserror::Error, Debug)]
 pub enum RouteError {
     #[error(transparent)]
-    UnexpectedError(#[from] eyre::Error),
+    UnexpectedError(eyre::Error),
+    #[error("RPC node unavailable: {0}")]
+    RpcUnavailable(eyre::Error),
+    #[error(transparent)]
+    PriceSourceUnavailable(eyre::Error),
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error("Forbidden")]
+    Forbidden,
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("Not found")]
+    NotFound,
 }
 
 impl ResponseError for RouteError {
     fn status_code(&self) -> StatusCode {
         match self {
             RouteError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            RouteError::RpcUnavailable(_) | RouteError::PriceSourceUnavailable(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            RouteError::Unauthorized => StatusCode::UNAUTHORIZED,
+            RouteError::Forbidden => StatusCode::FORBIDDEN,
+            RouteError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            RouteError::NotFound => StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+impl From<eyre::Error> for RouteError {
+    fn from(report: eyre::Error) -> Self {
+        if let Some(InvalidRequest(reason)) = report.downcast_ref() {
+            return RouteError::BadRequest(reason.clone());
+        }
+        if report.is::<PriceSourceUnavailable>() {
+            return RouteError::PriceSourceUnavailable(report);
+        }
+        // Rows outside the caller's merchant are not found either.
+        if let Some(diesel::result::Error::NotFound) = report.downcast_ref() {
+            return RouteError::NotFound;
         }
+        // Errors answered by the node itself, like reverts, stay unexpected.
+        match report.downcast_ref::<TransportError>() {
+            Some(RpcError::Transport(_)) => RouteError::RpcUnavailable(report),
+            _ => RouteError::UnexpectedError(report),
+        }
+    }
+}
+
+impl From<BlockingError> for RouteError {
+    fn from(error: BlockingError) -> Self {
+        RouteError::UnexpectedError(error.into())
     }
 }
 
 pub async fn get_invoice_by_status(
     path: web::Path<(u32,)>,
+    caller: Caller,
     ctx: web::Data<AppState>,
 ) -> Result<impl Responder, RouteError> {
-    let mut mgr_lock = ctx.invoice_manager.lock().await;
-    let data = mgr_lock.get_invoice_by_int_state(path.into_inner().0)?;
-    Ok(web::Json(data))
+    let state = InvoiceState::from_int(path.into_inner().0);
+    let invoice_service = ctx.invoice_service.clone();
+    let data =
+        web::block(move || invoice_service.get_invoices_by_state(caller.merchant, state)).await??;
+    Ok(web::Json(
+        data.into_iter()
+            .map(InvoiceResponse::from)
+            .collect::<Vec<_>>(),
+    ))
 }
 
 pub async fn get_invoice_by_action(
     path: web::Path<(u32,)>,
+    caller: Caller,
     ctx: web::Data<AppState>,
 ) -> Result<impl Responder, RouteError> {
-    let data = ctx
-        .invoice_manager
-        .lock()
-        .await
-        .get_invoice_by_int_action(path.into_inner().0)?;
-    Ok(web::Json(data))
+    let action = InvoiceAction::from_int(path.into_inner().0);
+    let invoice_service = ctx.invoice_service.clone();
+    let data = web::block(move || invoice_service.get_invoices_by_action(caller.merchant, action))
+        .await??;
+    Ok(web::Json(
+        data.into_iter()
+            .map(InvoiceResponse::from)
+            .collect::<Vec<_>>(),
+    ))
 }
 
 pub async fn get_invoice_by_address(
     path: web::Path<(String,)>,
+    caller: Caller,
     ctx: web::Data<AppState>,
 ) -> Result<impl Responder, RouteError> {
-    let invoice = ctx
-        .invoice_manager
-        .lock()
-        .await
-        .get_invoice_by_address(path.into_inner().0)?;
-    Ok(web::Json(invoice))
+    let address = path.into_inner().0;
+    let invoice_service = ctx.invoice_service.clone();
+    let (invoice, payments, sweep) = web::block(move || -> eyre::Result<_> {
+        Ok((
+            invoice_service.get_invoice_by_address(caller.merchant, address.clone())?,
+            invoice_service.get_payments(address.clone())?,
+            invoice_service.get_sweep(address)?,
+        ))
+    })
+    .await??;
+    Ok(web::Json(InvoiceDetailsResponse {
+        invoice: InvoiceResponse::from(invoice),
+        payments: payments.into_iter().map(PaymentResponse::from).collect(),
+        sweep: sweep.map(SweepResponse::from),
+    }))
 }
 
 #[derive(Deserialize)]
 pub struct CreateInvoice {
-    receiver: String,
-    value: f64,
-    lifetime: u64,
+    receiver: Option<String>,
+    value: Option<String>,
+    fiat_value: Option<String>,
+    lifetime: Option<u64>,
     action: Option<u32>,
+    token: Option<String>,
+    confirmations: Option<u64>,
+    webhook_url: Option<String>,
 }
 
+/// Invoices created with a merchant key belong to its merchant and fall back
+/// to the merchant defaults for left out fields.
 pub async fn create_invoice(
     data: web::Json<CreateInvoice>,
+    caller: Caller,
     ctx: web::Data<AppState>,
-) -> impl Responder {
-    let address = ctx
-        .invoice_manager
-        .lock()
-        .await
-        .create_invoice(
-            data.receiver.clone(),
-            data.value.clone(),
-            data.lifetime.clone(),
-            data.action.clone(),
-        )
-        .await;
-    match address {
-        Ok(address) => HttpResponse::Ok().body(address),
-        Err(_) => HttpResponse::InternalServerError().body("Failed to create invoice"),
-    }
+) -> Result<impl Responder, RouteError> {
+    let data = data.into_inner();
+    let value = match (data.value, data.fiat_value) {
+        (Some(value), None) => InvoiceValue::Crypto(value),
+        (None, Some(fiat_value)) => InvoiceValue::Fiat(fiat_value),
+        _ => {
+            return Err(RouteError::BadRequest(
+                "Exactly one of value and fiat_value is required".to_string(),
+            ))
+        }
+    };
+    let defaults = match caller.merchant.merchant_id() {
+        Some(merchant_id) => {
+            let invoice_service = ctx.invoice_service.clone();
+            web::block(move || invoice_service.get_merchant(merchant_id))
+                .await??
+                .map(|merchant| merchant.defaults)
+                .unwrap_or_default()
+        }
+        None => MerchantDefaults::default(),
+    };
+    let request = InvoiceRequest {
+        receiver: data
+            .receiver
+            .or(defaults.receiver)
+            .ok_or_else(|| RouteError::BadRequest("receiver is required".to_string()))?,
+        value,
+        lifetime: data
+            .lifetime
+            .or(defaults.lifetime)
+            .ok_or_else(|| RouteError::BadRequest("lifetime is required".to_string()))?,
+        action: data
+            .action
+            .map(InvoiceAction::from_int)
+            .or(defaults.complete_action)
+            .unwrap_or(InvoiceAction::Nothing),
+        token: data.token,
+        confirmations: data.confirmations,
+        merchant_id: caller.merchant.merchant_id(),
+        webhook_url: data.webhook_url.or(defaults.webhook_url),
+    };
+
+    let address = InvoiceManager::create_invoice(ctx.invoice_manager.clone(), request).await?;
+    Ok(HttpResponse::Ok().body(address))
 }
 
 pub async fn manual_update(
     path: web::Path<(String,)>,
+    caller: Caller,
     ctx: web::Data<AppState>,
 ) -> Result<impl Responder, RouteError> {
-    let invoice_state = ctx
-        .invoice_manager
-        .lock()
-        .await
-        .manual_check(path.into_inner().0)
-        .await?;
+    let invoice_state = InvoiceManager::manual_check(
+        ctx.invoice_manager.clone(),
+        caller.merchant,
+        path.into_inner().0,
+    )
+    .await?;
     Ok(web::Json(invoice_state))
 }
+
+#[derive(Deserialize)]
+pub struct ExportKey {
+    password: String,
+}
+
+/// Admin only. Returns the invoice private key as an encrypted Web3 Secret
+/// Storage keystore. Every attempt is written to the audit log.
+pub async fn export_key(
+    path: web::Path<(String,)>,
+    data: web::Json<ExportKey>,
+    request: HttpRequest,
+    ctx: web::Data<AppState>,
+) -> Result<impl Responder, RouteError> {
+    let address = path.into_inner().0;
+    let caller = describe_caller(&request);
+
+    if data.password.is_empty() {
+        info!(target: "audit", "Key export of {address} by {caller} denied: empty password");
+        return Err(RouteError::BadRequest(
+            "Password must not be empty".to_string(),
+        ));
+    }
+
+    let invoice_service = ctx.invoice_service.clone();
+    let (export_address, password) = (address.clone(), data.password.clone());
+    let keystore = web::block(move || {
+        invoice_service
+            .get_invoice_by_address(MerchantScope::All, export_address)?
+            .export_keystore(&password)
+    })
+    .await?;
+    match keystore {
+        Ok(keystore) => {
+            info!(target: "audit", "Key export of {address} by {caller} succeeded");
+            Ok(HttpResponse::Ok()
+                .content_type(ContentType::json())
+                .body(keystore))
+        }
+        Err(report) => {
+            info!(target: "audit", "Key export of {address} by {caller} failed: {report}");
+            Err(report.into())
+        }
+    }
+}
+
+/// Admin only. Flags a pending sweep to be replaced by a zero value
+/// self-transfer. The cancel is broadcast by the process holding the invoice
+/// keys on its next pass.
+pub async fn cancel_sweep(
+    path: web::Path<(String,)>,
+    request: HttpRequest,
+    ctx: web::Data<AppState>,
+) -> Result<impl Responder, RouteError> {
+    let address = path.into_inner().0;
+    let caller = describe_caller(&request);
+
+    let invoice_service = ctx.invoice_service.clone();
+    let cancel_address = address.clone();
+    let requested =
+        web::block(move || invoice_service.request_sweep_cancel(cancel_address)).await??;
+    if !requested {
+        return Err(RouteError::BadRequest(format!(
+            "Invoice {address} has no pending sweep"
+        )));
+    }
+    info!(target: "audit", "Sweep cancel of {address} requested by {caller}");
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[derive(Deserialize)]
+pub struct CreateRescan {
+    from_block: u64,
+    to_block: u64,
+}
+
+/// Admin only. Queues a rescan of the block range for payments to known
+/// invoices. It is processed in chunks by the background loop.
+pub async fn create_rescan(
+    data: web::Json<CreateRescan>,
+    request: HttpRequest,
+    ctx: web::Data<AppState>,
+) -> Result<impl Responder, RouteError> {
+    let caller = describe_caller(&request);
+    let range = format!("{}..={}", data.from_block, data.to_block);
+
+    if data.from_block > data.to_block {
+        return Err(RouteError::BadRequest(
+            "from_block must not be after to_block".to_string(),
+        ));
+    }
+
+    let invoice_service = ctx.invoice_service.clone();
+    let (from_block, to_block) = (data.from_block, data.to_block);
+    let rescan = web::block(move || invoice_service.create_rescan(from_block, to_block)).await??;
+    info!(target: "audit", "Rescan {} of blocks {range} requested by {caller}", rescan.id);
+    Ok(web::Json(RescanResponse::from(rescan)))
+}
+
+/// Admin only. Returns the progress of a rescan.
+pub async fn get_rescan(
+    path: web::Path<(i32,)>,
+    ctx: web::Data<AppState>,
+) -> Result<impl Responder, RouteError> {
+    let id = path.into_inner().0;
+    let invoice_service = ctx.invoice_service.clone();
+    let rescan = web::block(move || invoice_service.get_rescan(id)).await??;
+    Ok(web::Json(RescanResponse::from(rescan)))
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiKey {
+    name: String,
+    scopes: Vec<String>,
+    /// Required for keys without admin scope.
+    merchant_id: Option<i32>,
+}
+
+/// Admin only. Issues an API key. The key is returned once, only its hash is
+/// stored.
+pub async fn create_api_key(
+    data: web::Json<CreateApiKey>,
+    request: HttpRequest,
+    ctx: web::Data<AppState>,
+) -> Result<impl Responder, RouteError> {
+    let caller = describe_caller(&request);
+    let CreateApiKey {
+        name,
+        scopes,
+        merchant_id,
+    } = data.into_inner();
+    if name.trim().is_empty() {
+        return Err(RouteError::BadRequest("Name must not be empty".to_string()));
+    }
+    let scopes = parse_scopes(&scopes)
+        .and_then(|scopes| check_merchant(&scopes, merchant_id).map(|_| scopes))
+        .map_err(|report| RouteError::BadRequest(report.to_string()))?;
+    if let Some(merchant_id) = merchant_id {
+        let invoice_service = ctx.invoice_service.clone();
+        if web::block(move || invoice_service.get_merchant(merchant_id))
+            .await??
+            .is_none()
+        {
+            return Err(RouteError::BadRequest(format!(
+                "Merchant {merchant_id} does not exist"
+            )));
+        }
+    }
+
+    let key = generate_key();
+    let key_hash = hash_key(&key);
+    let invoice_service = ctx.invoice_service.clone();
+    let api_key =
+        web::block(move || invoice_service.create_api_key(name, &scopes, merchant_id, key_hash))
+            .await??;
+    info!(target: "audit", "API key {} ({}) issued by {caller}", api_key.id, api_key.name);
+    Ok(web::Json(IssuedApiKeyResponse {
+        key,
+        api_key: ApiKeyResponse::from(api_key),
+    }))
+}
+
+/// Admin only. Lists issued API keys, including revoked ones.
+pub async fn get_api_keys(ctx: web::Data<AppState>) -> Result<impl Responder, RouteError> {
+    let invoice_service = ctx.invoice_service.clone();
+    let api_keys = web::block(move || invoice_service.list_api_keys()).await??;
+    Ok(web::Json(
+        api_keys
+            .into_iter()
+            .map(ApiKeyResponse::from)
+            .collect::<Vec<_>>(),
+    ))
+}
+
+/// Admin only. Revokes an API key, it is rejected from the next request on.
+pub async fn revoke_api_key(
+    path: web::Path<(i32,)>,
+    request: HttpRequest,
+    ctx: web::Data<AppState>,
+) -> Result<impl Responder, RouteError> {
+    let caller = describe_caller(&request);
+    let id = path.into_inner().0;
+    let invoice_service = ctx.invoice_service.clone();
+    let revoked = web::block(move || invoice_service.revoke_api_key(id)).await??;
+    if !revoked {
+        return Err(RouteError::BadRequest(format!(
+            "API key {id} does not exist or is already revoked"
+        )));
+    }
+    info!(target: "audit", "API key {id} revoked by {caller}");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize)]
+pub struct MerchantDefaultsBody {
+    receiver: Option<String>,
+    lifetime: Option<u64>,
+    action: Option<u32>,
+    webhook_url: Option<String>,
+}
+
+impl MerchantDefaultsBody {
+    fn into_defaults(self) -> Result<MerchantDefaults, RouteError> {
+        if let Some(receiver) = &self.receiver {
+            receiver.parse::<Address>().map_err(|_| {
+                RouteError::BadRequest(format!("Invalid receiver address {receiver}"))
+            })?;
+        }
+        Ok(MerchantDefaults {
+            receiver: self.receiver,
+            lifetime: self.lifetime,
+            complete_action: self.action.map(InvoiceAction::from_int),
+            webhook_url: self.webhook_url,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateMerchant {
+    name: String,
+    #[serde(flatten)]
+    defaults: MerchantDefaultsBody,
+}
+
+/// Admin only. Registers a merchant. Its invoices are created with merchant
+/// API keys.
+pub async fn create_merchant(
+    data: web::Json<CreateMerchant>,
+    request: HttpRequest,
+    ctx: web::Data<AppState>,
+) -> Result<impl Responder, RouteError> {
+    let caller = describe_caller(&request);
+    let CreateMerchant { name, defaults } = data.into_inner();
+    if name.trim().is_empty() {
+        return Err(RouteError::BadRequest("Name must not be empty".to_string()));
+    }
+    let defaults = defaults.into_defaults()?;
+
+    let invoice_service = ctx.invoice_service.clone();
+    let merchant = web::block(move || invoice_service.create_merchant(name, &defaults)).await??;
+    info!(target: "audit", "Merchant {} ({}) created by {caller}", merchant.id, merchant.name);
+    Ok(web::Json(MerchantResponse::from(merchant)))
+}
+
+/// Admin only.
+pub async fn get_merchants(ctx: web::Data<AppState>) -> Result<impl Responder, RouteError> {
+    let invoice_service = ctx.invoice_service.clone();
+    let merchants = web::block(move || invoice_service.list_merchants()).await??;
+    Ok(web::Json(
+        merchants
+            .into_iter()
+            .map(MerchantResponse::from)
+            .collect::<Vec<_>>(),
+    ))
+}
+
+/// Admin only. Replaces the merchant defaults. Existing invoices keep the
+/// values they were created with.
+pub async fn update_merchant(
+    path: web::Path<(i32,)>,
+    data: web::Json<MerchantDefaultsBody>,
+    request: HttpRequest,
+    ctx: web::Data<AppState>,
+) -> Result<impl Responder, RouteError> {
+    let caller = describe_caller(&request);
+    let id = path.into_inner().0;
+    let defaults = data.into_inner().into_defaults()?;
+
+    let invoice_service = ctx.invoice_service.clone();
+    let merchant = web::block(move || invoice_service.update_merchant_defaults(id, &defaults))
+        .await??
+        .ok_or_else(|| RouteError::BadRequest(format!("Merchant {id} does not exist")))?;
+    info!(target: "audit", "Merchant {id} defaults updated by {caller}");
+    Ok(web::Json(MerchantResponse::from(merchant)))
+}
+
+/// Counters in the Prometheus text format.
+pub async fn get_metrics() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_missing_rows_to_not_found() {
+        let error = RouteError::from(eyre::Report::new(diesel::result::Error::NotFound));
+        assert_eq!(error.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn maps_invalid_requests_to_bad_request() {
+        let error = RouteError::from(crate::utils::parse_amount("1 gwei", 6).unwrap_err());
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error.to_string(),
+            "gwei is only supported for 18 decimal amounts"
+        );
+    }
+
+    #[test]
+    fn maps_price_source_outages_to_service_unavailable() {
+        let error = RouteError::from(eyre::Error::from(PriceSourceUnavailable(eyre::eyre!(
+            "timed out"
+        ))));
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error.to_string(), "Price source unavailable: timed out");
+    }
+
+    #[test]
+    fn keeps_other_errors_unexpected() {
+        let error = RouteError::from(eyre::eyre!("Invoice is watch-only"));
+        assert_eq!(error.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,250 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use eyre::{eyre, Result};
+use std::collections::BTreeMap;
+
+const PREFIX: &str = "enc";
+const NONCE_LENGTH: usize = 12;
+
+/// Versioned key-encryption keys used to seal invoice mnemonics at rest.
+///
+/// Every secret is encrypted with its own random data key, which is in turn
+/// wrapped with the newest key of the ring. Stored values look like
+/// `enc:v<version>:<hex wrapped data key>:<hex ciphertext>`, so older key
+/// versions stay readable after a rotation until rows are re-encrypted.
+pub struct KeyRing {
+    keys: BTreeMap<u32, Key<Aes256Gcm>>,
+}
+
+impl KeyRing {
+    /// Parses `<version>=<hex key>` entries separated by commas or newlines.
+    pub fn parse(data: &str) -> Result<Self> {
+        let mut keys = BTreeMap::new();
+        for entry in data
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (version, key) = entry
+                .split_once('=')
+                .ok_or_else(|| eyre!("Encryption key entry must be <version>=<hex key>"))?;
+            let key = hex::decode(key.trim())?;
+            if key.len() != 32 {
+                return Err(eyre!("Encryption key {} must be 32 bytes", version));
+            }
+            keys.insert(version.trim().parse()?, *Key::<Aes256Gcm>::from_slice(&key));
+        }
+        if keys.is_empty() {
+            return Err(eyre!("No encryption keys configured"));
+        }
+        Ok(Self { keys })
+    }
+
+    /// Reads keys from the file at `ENCRYPTION_KEYS_FILE` or from
+    /// `ENCRYPTION_KEYS`. Returns `None` if neither is set.
+    pub fn from_env() -> Result<Option<Self>> {
+        if let Ok(path) = std::env::var("ENCRYPTION_KEYS_FILE") {
+            return Ok(Some(Self::parse(&std::fs::read_to_string(path)?)?));
+        }
+        if let Ok(keys) = std::env::var("ENCRYPTION_KEYS") {
+            return Ok(Some(Self::parse(&keys)?));
+        }
+        Ok(None)
+    }
+
+    fn current_version(&self) -> u32 {
+        *self.keys.keys().next_back().unwrap()
+    }
+
+    pub fn is_encrypted(stored: &str) -> bool {
+        stored.starts_with(PREFIX)
+    }
+
+    /// Whether `stored` is already sealed with the newest key.
+    pub fn is_current(&self, stored: &str) -> bool {
+        stored.starts_with(&format!("{}:v{}:", PREFIX, self.current_version()))
+    }
+
+    /// Encrypts `plaintext` bound to `associated_data`, which has to be
+    /// supplied again on decryption.
+    pub fn encrypt(&self, plaintext: &str, associated_data: &str) -> Result<String> {
+        let version = self.current_version();
+        let data_key = Aes256Gcm::generate_key(OsRng);
+
+        let ciphertext = seal(&data_key, plaintext.as_bytes(), associated_data.as_bytes())?;
+        let wrapped_key = seal(&self.keys[&version], &data_key, &version.to_be_bytes())?;
+
+        Ok(format!(
+            "{}:v{}:{}:{}",
+            PREFIX,
+            version,
+            hex::encode(wrapped_key),
+            hex::encode(ciphertext)
+        ))
+    }
+
+    /// Decrypts a value produced by [`KeyRing::encrypt`]. Plaintext values
+    /// written before encryption was enabled are returned unchanged.
+    pub fn decrypt(&self, stored: &str, associated_data: &str) -> Result<String> {
+        if !Self::is_encrypted(stored) {
+            return Ok(stored.to_string());
+        }
+
+        let parts: Vec<&str> = stored.split(':').collect();
+        let [_, version, wrapped_key, ciphertext] = parts[..] else {
+            return Err(eyre!("Malformed encrypted value"));
+        };
+        let version: u32 = version.trim_start_matches('v').parse()?;
+        let key = self
+            .keys
+            .get(&version)
+            .ok_or_else(|| eyre!("Encryption key version {} is not configured", version))?;
+
+        let data_key = open(key, &hex::decode(wrapped_key)?, &version.to_be_bytes())?;
+        let plaintext = open(
+            Key::<Aes256Gcm>::from_slice(&data_key),
+            &hex::decode(ciphertext)?,
+            associated_data.as_bytes(),
+        )?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+}
+
+/// Returns the nonce followed by the ciphertext.
+fn seal(key: &Key<Aes256Gcm>, plaintext: &[u8], associated_data: &[u8]) -> Result<Vec<u8>> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = Aes256Gcm::new(key)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: associated_data,
+            },
+        )
+        .map_err(|_| eyre!("Encryption failed"))?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn open(key: &Key<Aes256Gcm>, sealed: &[u8], associated_data: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LENGTH {
+        return Err(eyre!("Malformed encrypted value"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+    Aes256Gcm::new(key)
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: associated_data,
+            },
+        )
+        .map_err(|_| eyre!("Decryption failed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_1: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+    const KEY_2: &str = "2222222222222222222222222222222222222222222222222222222222222222";
+    const MNEMONIC: &str = "test test test test test test test test test test test junk";
+    const ADDRESS: &str = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266";
+
+    fn ring(keys: &str) -> KeyRing {
+        KeyRing::parse(keys).unwrap()
+    }
+
+    #[test]
+    fn round_trips() {
+        let ring = ring(&format!("1={KEY_1}"));
+        let stored = ring.encrypt(MNEMONIC, ADDRESS).unwrap();
+        assert!(KeyRing::is_encrypted(&stored));
+        assert!(stored.starts_with("enc:v1:"));
+        assert!(!stored.contains(MNEMONIC));
+        assert_eq!(ring.decrypt(&stored, ADDRESS).unwrap(), MNEMONIC);
+        // Every value gets its own data key and nonces.
+        assert_ne!(ring.encrypt(MNEMONIC, ADDRESS).unwrap(), stored);
+    }
+
+    #[test]
+    fn rejects_value_moved_to_another_invoice() {
+        let ring = ring(&format!("1={KEY_1}"));
+        let stored = ring.encrypt(MNEMONIC, ADDRESS).unwrap();
+        assert!(ring
+            .decrypt(&stored, "0x70997970C51812dc3A010C7d01b50e0d17dc79C8")
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_values() {
+        let ring = ring(&format!("1={KEY_1}"));
+        let stored = ring.encrypt(MNEMONIC, ADDRESS).unwrap();
+        let parts: Vec<&str> = stored.split(':').collect();
+
+        let mut ciphertext = hex::decode(parts[3]).unwrap();
+        *ciphertext.last_mut().unwrap() ^= 1;
+        let tampered = format!(
+            "{}:{}:{}:{}",
+            parts[0],
+            parts[1],
+            parts[2],
+            hex::encode(ciphertext)
+        );
+        assert!(ring.decrypt(&tampered, ADDRESS).is_err());
+
+        let mut wrapped_key = hex::decode(parts[2]).unwrap();
+        wrapped_key[NONCE_LENGTH] ^= 1;
+        let tampered = format!(
+            "{}:{}:{}:{}",
+            parts[0],
+            parts[1],
+            hex::encode(wrapped_key),
+            parts[3]
+        );
+        assert!(ring.decrypt(&tampered, ADDRESS).is_err());
+
+        // The wrapped key is bound to its version.
+        let relabelled = stored.replacen("enc:v1:", "enc:v2:", 1);
+        let rotated = self::ring(&format!("1={KEY_1},2={KEY_1}"));
+        assert!(rotated.decrypt(&relabelled, ADDRESS).is_err());
+
+        assert!(ring.decrypt("enc:v1:00", ADDRESS).is_err());
+        assert!(ring.decrypt("enc:v1:00:00", ADDRESS).is_err());
+    }
+
+    #[test]
+    fn old_versions_stay_readable_after_rotation() {
+        let old_ring = ring(&format!("1={KEY_1}"));
+        let stored = old_ring.encrypt(MNEMONIC, ADDRESS).unwrap();
+
+        let rotated = ring(&format!("1={KEY_1}\n2={KEY_2}"));
+        assert!(old_ring.is_current(&stored));
+        assert!(!rotated.is_current(&stored));
+        assert_eq!(rotated.decrypt(&stored, ADDRESS).unwrap(), MNEMONIC);
+
+        let resealed = rotated.encrypt(MNEMONIC, ADDRESS).unwrap();
+        assert!(rotated.is_current(&resealed));
+        assert!(resealed.starts_with("enc:v2:"));
+
+        // Dropping a version that is still in use makes its rows unreadable.
+        assert!(ring(&format!("2={KEY_2}"))
+            .decrypt(&stored, ADDRESS)
+            .is_err());
+    }
+
+    #[test]
+    fn passes_legacy_plaintext_through() {
+        let ring = ring(&format!("1={KEY_1}"));
+        assert!(!KeyRing::is_encrypted(MNEMONIC));
+        assert!(!ring.is_current(MNEMONIC));
+        assert_eq!(ring.decrypt(MNEMONIC, ADDRESS).unwrap(), MNEMONIC);
+    }
+
+    #[test]
+    fn rejects_invalid_key_rings() {
+        assert!(KeyRing::parse("").is_err());
+        assert!(KeyRing::parse(KEY_1).is_err());
+        assert!(KeyRing::parse("1=1111").is_err());
+        assert!(KeyRing::parse(&format!("one={KEY_1}")).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use crate::api_keys::ApiKey;
+use crate::invoices::{Invoice, InvoiceAction, InvoiceState};
+use crate::merchants::Merchant;
+use crate::payments::Payment;
+use crate::rescans::Rescan;
+use crate::sweeps::Sweep;
+use serde::Serialize;
+
+/// Public view of an invoice. Never carries wallet secrets. Amounts are
+/// decimal strings in base units (wei for ETH invoices).
+#[derive(Serialize)]
+pub struct InvoiceResponse {
+    pub address: String,
+    pub receiver: String,
+    pub value: String,
+    pub state: InvoiceState,
+    pub lifetime: u64,
+    pub complete_action: InvoiceAction,
+    pub confirmations: u64,
+    pub token: Option<String>,
+    pub decimals: u8,
+    pub top_up_cost: String,
+    /// Unmined transfer while the invoice is PaymentPending.
+    pub pending_tx_hash: Option<String>,
+    pub pending_amount: Option<String>,
+    pub merchant_id: Option<i32>,
+    pub webhook_url: Option<String>,
+    /// Set on invoices created in fiat. `value` was quoted at
+    /// `exchange_rate`, the fiat price of one whole coin or token.
+    pub fiat_amount: Option<String>,
+    pub fiat_currency: Option<String>,
+    pub exchange_rate: Option<String>,
+    pub rate_timestamp: Option<u64>,
+}
+
+impl From<Invoice> for InvoiceResponse {
+    fn from(invoice: Invoice) -> Self {
+        Self {
+            address: invoice.address,
+            receiver: invoice.receiver,
+            value: invoice.value.to_string(),
+            state: invoice.state,
+            lifetime: invoice.lifetime,
+            complete_action: invoice.complete_action,
+            confirmations: invoice.confirmations,
+            token: invoice.token,
+            decimals: invoice.decimals,
+            top_up_cost: invoice.top_up_cost.to_string(),
+            pending_tx_hash: invoice
+                .pending_payment
+                .as_ref()
+                .map(|payment| payment.tx_hash.clone()),
+            pending_amount: invoice
+                .pending_payment
+                .map(|payment| payment.amount.to_string()),
+            merchant_id: invoice.merchant_id,
+            webhook_url: invoice.webhook_url,
+            fiat_amount: invoice.fiat.as_ref().map(|fiat| fiat.amount.to_string()),
+            fiat_currency: invoice.fiat.as_ref().map(|fiat| fiat.currency.clone()),
+            exchange_rate: invoice.fiat.as_ref().map(|fiat| fiat.rate.to_string()),
+            rate_timestamp: invoice.fiat.map(|fiat| fiat.timestamp),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PaymentResponse {
+    pub tx_hash: String,
+    pub block_number: u64,
+    pub sender: String,
+    pub amount: String,
+    pub log_index: Option<u64>,
+}
+
+impl From<Payment> for PaymentResponse {
+    fn from(payment: Payment) -> Self {
+        Self {
+            tx_hash: payment.tx_hash,
+            block_number: payment.block_number,
+            sender: payment.sender,
+            amount: payment.amount.to_string(),
+            log_index: payment.log_index,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct InvoiceDetailsResponse {
+    #[serde(flatten)]
+    pub invoice: InvoiceResponse,
+    pub payments: Vec<PaymentResponse>,
+    pub sweep: Option<SweepResponse>,
+}
+
+#[derive(Serialize)]
+pub struct SweepResponse {
+    pub tx_hash: Option<String>,
+    pub nonce: Option<u64>,
+    pub status: u32,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+impl From<Sweep> for SweepResponse {
+    fn from(sweep: Sweep) -> Self {
+        Self {
+            tx_hash: sweep.tx_hash,
+            nonce: sweep.nonce,
+            status: sweep.status.to_int(),
+            attempts: sweep.attempts,
+            last_error: sweep.last_error,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RescanResponse {
+    pub id: i32,
+    pub from_block: u64,
+    pub to_block: u64,
+    pub next_block: u64,
+    pub status: u32,
+    pub missed_payments: u32,
+    pub repaired_invoices: u32,
+}
+
+impl From<Rescan> for RescanResponse {
+    fn from(rescan: Rescan) -> Self {
+        Self {
+            id: rescan.id,
+            from_block: rescan.from_block,
+            to_block: rescan.to_block,
+            next_block: rescan.next_block,
+            status: rescan.status.to_int(),
+            missed_payments: rescan.missed_payments,
+            repaired_invoices: rescan.repaired_invoices,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyResponse {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: u64,
+    pub revoked_at: Option<u64>,
+    pub merchant_id: Option<i32>,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(api_key: ApiKey) -> Self {
+        Self {
+            id: api_key.id,
+            name: api_key.name,
+            scopes: api_key
+                .scopes
+                .iter()
+                .map(|scope| scope.as_str().to_string())
+                .collect(),
+            created_at: api_key.created_at,
+            revoked_at: api_key.revoked_at,
+            merchant_id: api_key.merchant_id,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct IssuedApiKeyResponse {
+    /// Shown only once.
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
+
+#[derive(Serialize)]
+pub struct MerchantResponse {
+    pub id: i32,
+    pub name: String,
+    pub receiver: Option<String>,
+    pub lifetime: Option<u64>,
+    pub complete_action: Option<InvoiceAction>,
+    pub webhook_url: Option<String>,
+    pub created_at: u64,
+}
+
+impl From<Merchant> for MerchantResponse {
+    fn from(merchant: Merchant) -> Self {
+        Self {
+            id: merchant.id,
+            name: merchant.name,
+            receiver: merchant.defaults.receiver,
+            lifetime: merchant.defaults.lifetime,
+            complete_action: merchant.defaults.complete_action,
+            webhook_url: merchant.defaults.webhook_url,
+            created_at: merchant.created_at,
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use crate::invoices::ProviderArc;
+use alloy::eips::BlockId;
+use alloy::network::TransactionBuilder;
+use alloy::primitives::{Address, Bytes, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use alloy::sol;
+use alloy::sol_types::SolCall;
+use eyre::Result;
+
+sol! {
+    interface IERC20 {
+        event Transfer(address indexed from, address indexed to, uint256 value);
+
+        function balanceOf(address account) external view returns (uint256);
+        function decimals() external view returns (uint8);
+        function symbol() external view returns (string);
+        function transfer(address to, uint256 value) external returns (bool);
+    }
+}
+
+pub async fn balance_of(
+    provider_arc: ProviderArc,
+    token: Address,
+    account: Address,
+    block: BlockId,
+) -> Result<U256> {
+    let request = TransactionRequest::default()
+        .with_to(token)
+        .with_input(IERC20::balanceOfCall { account }.abi_encode());
+    let output = provider_arc.call(&request).block(block).await?;
+    Ok(IERC20::balanceOfCall::abi_decode_returns(&output, true)?._0)
+}
+
+pub async fn decimals(provider_arc: ProviderArc, token: Address) -> Result<u8> {
+    let request = TransactionRequest::default()
+        .with_to(token)
+        .with_input(IERC20::decimalsCall {}.abi_encode());
+    let output = provider_arc.call(&request).await?;
+    Ok(IERC20::decimalsCall::abi_decode_returns(&output, true)?._0)
+}
+
+pub async fn symbol(provider_arc: ProviderArc, token: Address) -> Result<String> {
+    let request = TransactionRequest::default()
+        .with_to(token)
+        .with_input(IERC20::symbolCall {}.abi_encode());
+    let output = provider_arc.call(&request).await?;
+    Ok(IERC20::symbolCall::abi_decode_returns(&output, true)?._0)
+}
+
+pub fn transfer_input(to: Address, value: U256) -> Bytes {
+    IERC20::transferCall { to, value }.abi_encode().into()
+}
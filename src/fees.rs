@@ -0,0 +1,165 @@
+use crate::invoices::ProviderArc;
+use crate::sweeps::SweepFees;
+use alloy::eips::BlockNumberOrTag;
+use alloy::providers::Provider;
+use eyre::{eyre, Result};
+use std::str::FromStr;
+
+/// Number of recent blocks whose priority fees are sampled.
+const FEE_HISTORY_BLOCKS: u64 = 20;
+
+/// How quickly a transaction should be included. Higher urgency pays a
+/// higher priority fee percentile and leaves more room for base fee growth.
+#[derive(Clone, Copy)]
+pub enum Urgency {
+    Low,
+    Medium,
+    High,
+}
+
+impl Urgency {
+    fn reward_percentile(&self) -> f64 {
+        match self {
+            Self::Low => 10.0,
+            Self::Medium => 50.0,
+            Self::High => 90.0,
+        }
+    }
+
+    /// Maximum base fee as a multiple of the next block base fee, in eighths.
+    fn base_fee_multiplier(&self) -> u128 {
+        match self {
+            Self::Low => 10,
+            Self::Medium => 12,
+            Self::High => 16,
+        }
+    }
+}
+
+impl FromStr for Urgency {
+    type Err = eyre::Report;
+
+    fn from_str(urgency: &str) -> Result<Self> {
+        match urgency.to_lowercase().as_str() {
+            "low" => Ok(Self::Low),
+            "medium" => Ok(Self::Medium),
+            "high" => Ok(Self::High),
+            _ => Err(eyre!(
+                "Unknown urgency {urgency}, expected low, medium or high"
+            )),
+        }
+    }
+}
+
+/// Estimates EIP-1559 fees from `eth_feeHistory`: the median of the recent
+/// priority fees at the urgency percentile, capped at `max_priority_fee`, on
+/// top of the next block base fee scaled by the urgency.
+pub async fn estimate_fees(
+    provider_arc: ProviderArc,
+    urgency: Urgency,
+    max_priority_fee: u128,
+) -> Result<SweepFees> {
+    let history = provider_arc
+        .get_fee_history(
+            FEE_HISTORY_BLOCKS,
+            BlockNumberOrTag::Latest,
+            &[urgency.reward_percentile()],
+        )
+        .await?;
+    let next_base_fee = history
+        .next_block_base_fee()
+        .ok_or_else(|| eyre!("Fee history has no base fee"))?;
+    let rewards = history
+        .reward
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|block_rewards| block_rewards.first().copied())
+        .collect();
+
+    Ok(fees_from_history(
+        next_base_fee,
+        rewards,
+        urgency,
+        max_priority_fee,
+    ))
+}
+
+/// Fees for the next block from its base fee and the sampled priority fees
+/// of recent blocks. Without samples the priority fee is `max_priority_fee`.
+fn fees_from_history(
+    next_base_fee: u128,
+    mut rewards: Vec<u128>,
+    urgency: Urgency,
+    max_priority_fee: u128,
+) -> SweepFees {
+    rewards.sort_unstable();
+    let priority_fee = rewards
+        .get(rewards.len() / 2)
+        .copied()
+        .unwrap_or(max_priority_fee)
+        .min(max_priority_fee);
+
+    SweepFees {
+        max_fee_per_gas: next_base_fee * urgency.base_fee_multiplier() / 8 + priority_fee,
+        max_priority_fee_per_gas: priority_fee,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GWEI: u128 = 1_000_000_000;
+
+    fn fees(max_fee_per_gas: u128, max_priority_fee_per_gas: u128) -> SweepFees {
+        SweepFees {
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+        }
+    }
+
+    #[test]
+    fn scales_base_fee_by_urgency() {
+        let rewards = vec![GWEI];
+        assert_eq!(
+            fees_from_history(80 * GWEI, rewards.clone(), Urgency::Low, 5 * GWEI),
+            fees(101 * GWEI, GWEI)
+        );
+        assert_eq!(
+            fees_from_history(80 * GWEI, rewards.clone(), Urgency::Medium, 5 * GWEI),
+            fees(121 * GWEI, GWEI)
+        );
+        assert_eq!(
+            fees_from_history(80 * GWEI, rewards, Urgency::High, 5 * GWEI),
+            fees(161 * GWEI, GWEI)
+        );
+    }
+
+    #[test]
+    fn takes_median_reward_capped_at_max_priority_fee() {
+        let rewards = vec![9 * GWEI, GWEI, 3 * GWEI, 2 * GWEI, 7 * GWEI];
+        assert_eq!(
+            fees_from_history(8 * GWEI, rewards.clone(), Urgency::Low, 5 * GWEI),
+            fees(13 * GWEI, 3 * GWEI)
+        );
+        assert_eq!(
+            fees_from_history(8 * GWEI, rewards, Urgency::Low, 2 * GWEI),
+            fees(12 * GWEI, 2 * GWEI)
+        );
+    }
+
+    #[test]
+    fn falls_back_to_max_priority_fee_without_rewards() {
+        assert_eq!(
+            fees_from_history(8 * GWEI, Vec::new(), Urgency::Medium, 2 * GWEI),
+            fees(14 * GWEI, 2 * GWEI)
+        );
+    }
+
+    #[test]
+    fn parses_urgency() {
+        assert!(matches!("HIGH".parse(), Ok(Urgency::High)));
+        assert!(matches!("low".parse(), Ok(Urgency::Low)));
+        assert!("urgent".parse::<Urgency>().is_err());
+    }
+}
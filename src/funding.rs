@@ -0,0 +1,63 @@
+use crate::invoices::ProviderArc;
+use crate::sweeps::SweepFees;
+use alloy::eips::BlockId;
+use alloy::network::{EthereumWallet, TransactionBuilder};
+use alloy::primitives::{Address, TxHash, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use alloy::signers::local::PrivateKeySigner;
+use eyre::Result;
+use log::info;
+
+const TRANSFER_GAS_LIMIT: u128 = 21000;
+
+pub struct FundingStation {
+    wallet: PrivateKeySigner,
+}
+
+impl FundingStation {
+    pub fn new(private_key: String) -> Result<Self> {
+        Ok(Self {
+            wallet: private_key.parse()?,
+        })
+    }
+
+    /// Broadcasts a transfer of `amount` wei to `address` without waiting for
+    /// it to be mined. `fees` are the ones estimated for the sweep it pays
+    /// for, so the top-up is not outbid when fees spike.
+    pub async fn top_up(
+        &self,
+        provider_arc: ProviderArc,
+        address: Address,
+        amount: U256,
+        fees: SweepFees,
+    ) -> Result<TxHash> {
+        let chain_id = provider_arc.get_chain_id().await?;
+        // Counts top-ups of other invoices that are not mined yet.
+        let nonce = provider_arc
+            .get_transaction_count(self.wallet.address())
+            .block_id(BlockId::pending())
+            .await?;
+
+        let built_transaction = TransactionRequest::default()
+            .with_to(address)
+            .with_value(amount)
+            .with_max_fee_per_gas(fees.max_fee_per_gas)
+            .with_max_priority_fee_per_gas(fees.max_priority_fee_per_gas)
+            .with_gas_limit(TRANSFER_GAS_LIMIT)
+            .with_chain_id(chain_id)
+            .with_nonce(nonce)
+            .build(&EthereumWallet::new(self.wallet.clone()))
+            .await?;
+
+        info!("Topping up {} with {} wei", address, amount);
+        let tx_hash = provider_arc
+            .send_tx_envelope(built_transaction)
+            .await?
+            .tx_hash()
+            .to_owned();
+        info!("Top-up transaction hash: {} for {}", tx_hash, address);
+
+        Ok(tx_hash)
+    }
+}
@@ -0,0 +1,153 @@
+use alloy::primitives::Address;
+use alloy::signers::local::coins_bip39::{English, Mnemonic};
+use alloy::signers::local::PrivateKeySigner;
+use coins_bip32::enc::{MainnetEncoder, XKeyEncoder};
+use coins_bip32::prelude::{Parent, SigningKey, VerifyingKey, XPriv, XPub};
+use eyre::{eyre, Result};
+
+const ACCOUNT_PATH: &str = "m/44'/60'/0'";
+
+/// BIP-44 account key all invoice wallets are derived from.
+///
+/// With an extended private key invoice wallets can sign their sweeps. With an
+/// extended public key only addresses are derived, so the server never holds
+/// spending keys and sweeps are left to the `signer` process.
+pub enum HdWallet {
+    Private(XPriv),
+    WatchOnly(XPub),
+}
+
+impl HdWallet {
+    pub fn from_mnemonic(phrase: &str) -> Result<Self> {
+        let master_key = Mnemonic::<English>::new_from_phrase(phrase)?.master_key(None)?;
+        Ok(Self::Private(master_key.derive_path(ACCOUNT_PATH)?))
+    }
+
+    pub fn from_xprv(xprv: &str) -> Result<Self> {
+        let master_key = MainnetEncoder::xpriv_from_base58(xprv)?;
+        Ok(Self::Private(master_key.derive_path(ACCOUNT_PATH)?))
+    }
+
+    /// Expects the account level (`m/44'/60'/0'`) extended public key.
+    pub fn from_xpub(xpub: &str) -> Result<Self> {
+        Ok(Self::WatchOnly(MainnetEncoder::xpub_from_base58(xpub)?))
+    }
+
+    /// Reads `MASTER_MNEMONIC`, `MASTER_XPRV` or `MASTER_XPUB`, in that order.
+    pub fn from_env() -> Result<Self> {
+        if let Ok(phrase) = std::env::var("MASTER_MNEMONIC") {
+            return Self::from_mnemonic(&phrase);
+        }
+        if let Ok(xprv) = std::env::var("MASTER_XPRV") {
+            return Self::from_xprv(&xprv);
+        }
+        if let Ok(xpub) = std::env::var("MASTER_XPUB") {
+            return Self::from_xpub(&xpub);
+        }
+        Err(eyre!(
+            "MASTER_MNEMONIC, MASTER_XPRV or MASTER_XPUB must be set"
+        ))
+    }
+
+    pub fn is_watch_only(&self) -> bool {
+        matches!(self, Self::WatchOnly(_))
+    }
+
+    pub fn address(&self, index: u32) -> Result<Address> {
+        match self {
+            Self::Private(account_key) => {
+                let key = account_key.derive_path(address_path(index).as_str())?;
+                let signing_key: &SigningKey = key.as_ref();
+                Ok(Address::from_private_key(signing_key))
+            }
+            Self::WatchOnly(account_key) => {
+                let key = account_key.derive_path(address_path(index).as_str())?;
+                let verifying_key: &VerifyingKey = key.as_ref();
+                Ok(Address::from_public_key(verifying_key))
+            }
+        }
+    }
+
+    /// Returns `None` for watch-only wallets.
+    pub fn signer(&self, index: u32) -> Result<Option<PrivateKeySigner>> {
+        match self {
+            Self::Private(account_key) => {
+                let key = account_key.derive_path(address_path(index).as_str())?;
+                let signing_key: &SigningKey = key.as_ref();
+                Ok(Some(PrivateKeySigner::from_signing_key(
+                    signing_key.clone(),
+                )))
+            }
+            Self::WatchOnly(_) => Ok(None),
+        }
+    }
+}
+
+fn address_path(index: u32) -> String {
+    format!("0/{index}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::signers::local::MnemonicBuilder;
+
+    const PHRASE: &str = "test test test test test test test test test test test junk";
+    const ADDRESSES: [&str; 2] = [
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        "0x70997970C51812dc3A010C7d01b50e0d17dc79C8",
+    ];
+
+    fn account_xpub() -> String {
+        let master_key = Mnemonic::<English>::new_from_phrase(PHRASE)
+            .unwrap()
+            .master_key(None)
+            .unwrap();
+        let account_key = master_key.derive_path(ACCOUNT_PATH).unwrap();
+        MainnetEncoder::xpub_to_base58(&account_key.verify_key()).unwrap()
+    }
+
+    fn master_xprv() -> String {
+        let master_key = Mnemonic::<English>::new_from_phrase(PHRASE)
+            .unwrap()
+            .master_key(None)
+            .unwrap();
+        MainnetEncoder::xpriv_to_base58(&master_key).unwrap()
+    }
+
+    #[test]
+    fn watch_only_addresses_match_signing_wallets() {
+        let wallets = [
+            HdWallet::from_mnemonic(PHRASE).unwrap(),
+            HdWallet::from_xprv(&master_xprv()).unwrap(),
+            HdWallet::from_xpub(&account_xpub()).unwrap(),
+        ];
+        for (index, expected) in ADDRESSES.iter().enumerate() {
+            let expected: Address = expected.parse().unwrap();
+            // m/44'/60'/0'/0/{index}, derived independently of `HdWallet`.
+            let signer = MnemonicBuilder::<English>::default()
+                .phrase(PHRASE)
+                .index(index as u32)
+                .unwrap()
+                .build()
+                .unwrap();
+            assert_eq!(signer.address(), expected);
+
+            for wallet in &wallets {
+                assert_eq!(wallet.address(index as u32).unwrap(), expected);
+            }
+            for wallet in &wallets[..2] {
+                let signer = wallet.signer(index as u32).unwrap().unwrap();
+                assert_eq!(signer.address(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn watch_only_wallets_have_no_signer() {
+        let wallet = HdWallet::from_xpub(&account_xpub()).unwrap();
+        assert!(wallet.is_watch_only());
+        assert!(wallet.signer(0).unwrap().is_none());
+        assert!(!HdWallet::from_mnemonic(PHRASE).unwrap().is_watch_only());
+    }
+}
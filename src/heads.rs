@@ -0,0 +1,45 @@
+use alloy::providers::{Provider, ProviderBuilder};
+use alloy::transports::ws::WsConnect;
+use eyre::Result;
+use futures_util::StreamExt;
+use log::{error, info};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Subscribes to new blocks over WebSocket and notifies `new_head` on every
+/// block. Reconnects with exponential backoff when the subscription ends; the
+/// invoice loop keeps polling over HTTP in the meantime.
+pub fn start_head_listener(ws_url: String, new_head: Arc<Notify>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut reconnect_delay = MIN_RECONNECT_DELAY;
+        loop {
+            match subscribe(&ws_url, &new_head, &mut reconnect_delay).await {
+                Ok(_) => error!("New heads subscription closed"),
+                Err(report) => error!("New heads subscription failed: {report}"),
+            }
+            info!(
+                "Polling over HTTP, reconnecting to WebSocket in {}s",
+                reconnect_delay.as_secs()
+            );
+            tokio::time::sleep(reconnect_delay).await;
+            reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    })
+}
+
+async fn subscribe(ws_url: &str, new_head: &Notify, reconnect_delay: &mut Duration) -> Result<()> {
+    let provider = ProviderBuilder::new().on_ws(WsConnect::new(ws_url)).await?;
+    let mut blocks = provider.subscribe_blocks().await?.into_stream();
+    info!("Subscribed to new heads");
+
+    while blocks.next().await.is_some() {
+        *reconnect_delay = MIN_RECONNECT_DELAY;
+        new_head.notify_one();
+    }
+    Ok(())
+}
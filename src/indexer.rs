@@ -0,0 +1,321 @@
+use crate::erc20::IERC20;
+use crate::invoices::ProviderArc;
+use crate::payments::Payment;
+use alloy::primitives::{Address, B256};
+use alloy::providers::Provider;
+use alloy::rpc::types::{Filter, Log, Transaction};
+use alloy::sol_types::SolEvent;
+use eyre::{eyre, Result};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+
+/// Number of indexed block hashes kept to detect reorgs.
+pub const REORG_DEPTH: u64 = 128;
+
+/// Open invoice addresses the indexer matches transfers against.
+#[derive(Default)]
+pub struct WatchedAddresses {
+    native: HashSet<Address>,
+    /// Invoice addresses by the token they expect.
+    tokens: HashMap<Address, HashSet<Address>>,
+}
+
+impl WatchedAddresses {
+    /// Builds the set from `(invoice address, token)` pairs.
+    pub fn new(invoices: Vec<(String, Option<String>)>) -> Result<Self> {
+        let mut watched = Self::default();
+        for (address, token) in invoices {
+            let address = address.parse::<Address>()?;
+            match token {
+                Some(token) => {
+                    watched
+                        .tokens
+                        .entry(token.parse()?)
+                        .or_default()
+                        .insert(address);
+                }
+                None => {
+                    watched.native.insert(address);
+                }
+            }
+        }
+        Ok(watched)
+    }
+
+    pub fn is_watched(&self, address: &Address) -> bool {
+        self.native.contains(address)
+    }
+
+    pub fn is_watched_token(&self, token: &Address, address: &Address) -> bool {
+        self.tokens
+            .get(token)
+            .is_some_and(|addresses| addresses.contains(address))
+    }
+}
+
+pub struct ScannedBlock {
+    pub number: u64,
+    pub hash: B256,
+    pub parent_hash: B256,
+    pub payments: Vec<Payment>,
+}
+
+/// Collects ETH and token transfers to watched addresses in block `number`.
+pub async fn scan_block(
+    provider_arc: ProviderArc,
+    number: u64,
+    watched: &WatchedAddresses,
+) -> Result<ScannedBlock> {
+    let block = provider_arc
+        .get_block_by_number(number.into(), !watched.native.is_empty())
+        .await?
+        .ok_or_else(|| eyre!("Block {number} not found"))?;
+    let hash = block
+        .header
+        .hash
+        .ok_or_else(|| eyre!("Block {number} has no hash"))?;
+
+    let mut payments: Vec<Payment> = block
+        .transactions
+        .txns()
+        .filter_map(|transaction| native_payment(number, transaction, watched))
+        .collect();
+
+    if !watched.tokens.is_empty() {
+        let recipients: Vec<B256> = watched
+            .tokens
+            .values()
+            .flatten()
+            .map(|address| address.into_word())
+            .collect();
+        // Queried by hash so that logs belong to the block fetched above even
+        // if the chain reorganizes in between.
+        let filter = Filter::new()
+            .address(watched.tokens.keys().copied().collect::<Vec<_>>())
+            .event_signature(IERC20::Transfer::SIGNATURE_HASH)
+            .topic2(recipients)
+            .at_block_hash(hash);
+        for log in provider_arc.get_logs(&filter).await? {
+            payments.extend(token_payment(number, &log, watched)?);
+        }
+    }
+
+    Ok(ScannedBlock {
+        number,
+        hash,
+        parent_hash: block.header.parent_hash,
+        payments,
+    })
+}
+
+/// Payment of a transaction sending ETH to a watched address.
+fn native_payment(
+    number: u64,
+    transaction: &Transaction,
+    watched: &WatchedAddresses,
+) -> Option<Payment> {
+    let to = transaction.to?;
+    if !watched.is_watched(&to) || transaction.value.is_zero() {
+        return None;
+    }
+    Some(Payment {
+        invoice_address: to.to_string(),
+        tx_hash: transaction.hash.to_string(),
+        block_number: number,
+        sender: transaction.from.to_string(),
+        amount: transaction.value,
+        log_index: None,
+    })
+}
+
+/// Payment of a `Transfer` log of the token a watched address expects.
+fn token_payment(number: u64, log: &Log, watched: &WatchedAddresses) -> Result<Option<Payment>> {
+    let transfer = IERC20::Transfer::decode_log(&log.inner, true)?;
+    if !watched.is_watched_token(&log.address(), &transfer.to) {
+        return Ok(None);
+    }
+    Ok(Some(Payment {
+        invoice_address: transfer.to.to_string(),
+        tx_hash: log
+            .transaction_hash
+            .ok_or_else(|| eyre!("Log without transaction hash"))?
+            .to_string(),
+        block_number: number,
+        sender: transfer.from.to_string(),
+        amount: transfer.value,
+        log_index: log.log_index,
+    }))
+}
+
+/// Walks back from `from` to the newest block whose indexed hash is still
+/// canonical. A block that is not indexed, like one older than
+/// `REORG_DEPTH`, is taken as the ancestor.
+pub async fn common_ancestor<I, IF, C, CF>(
+    from: u64,
+    mut indexed_hash: I,
+    mut canonical_hash: C,
+) -> Result<u64>
+where
+    I: FnMut(u64) -> IF,
+    IF: Future<Output = Result<Option<String>>>,
+    C: FnMut(u64) -> CF,
+    CF: Future<Output = Result<B256>>,
+{
+    let mut number = from;
+    while let Some(hash) = indexed_hash(number).await? {
+        if number == 0 || hash == canonical_hash(number).await?.to_string() {
+            break;
+        }
+        number -= 1;
+    }
+    Ok(number)
+}
+
+pub async fn block_hash(provider_arc: ProviderArc, number: u64) -> Result<B256> {
+    provider_arc
+        .get_block_by_number(number.into(), false)
+        .await?
+        .and_then(|block| block.header.hash)
+        .ok_or_else(|| eyre!("Block {number} not found"))
+}
+
+pub async fn block_timestamp(provider_arc: ProviderArc, number: u64) -> Result<u64> {
+    provider_arc
+        .get_block_by_number(number.into(), false)
+        .await?
+        .map(|block| block.header.timestamp)
+        .ok_or_else(|| eyre!("Block {number} not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::{LogData, U256};
+
+    const INVOICE: Address = Address::repeat_byte(1);
+    const TOKEN_INVOICE: Address = Address::repeat_byte(2);
+    const TOKEN: Address = Address::repeat_byte(3);
+    const OTHER_TOKEN: Address = Address::repeat_byte(4);
+    const SENDER: Address = Address::repeat_byte(5);
+
+    fn watched() -> WatchedAddresses {
+        WatchedAddresses::new(vec![
+            (INVOICE.to_string(), None),
+            (TOKEN_INVOICE.to_string(), Some(TOKEN.to_string())),
+        ])
+        .unwrap()
+    }
+
+    fn transaction(to: Address, value: u64) -> Transaction {
+        Transaction {
+            hash: B256::repeat_byte(9),
+            from: SENDER,
+            to: Some(to),
+            value: U256::from(value),
+            ..Default::default()
+        }
+    }
+
+    fn transfer_log(token: Address, to: Address, value: u64) -> Log {
+        let transfer = IERC20::Transfer {
+            from: SENDER,
+            to,
+            value: U256::from(value),
+        };
+        let data: LogData = transfer.encode_log_data();
+        Log {
+            inner: alloy::primitives::Log {
+                address: token,
+                data,
+            },
+            transaction_hash: Some(B256::repeat_byte(9)),
+            log_index: Some(7),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn watches_native_and_token_addresses_separately() {
+        let watched = watched();
+        assert!(watched.is_watched(&INVOICE));
+        assert!(!watched.is_watched(&TOKEN_INVOICE));
+        assert!(watched.is_watched_token(&TOKEN, &TOKEN_INVOICE));
+        assert!(!watched.is_watched_token(&TOKEN, &INVOICE));
+        assert!(!watched.is_watched_token(&OTHER_TOKEN, &TOKEN_INVOICE));
+        assert!(WatchedAddresses::new(vec![("0x1234".to_string(), None)]).is_err());
+    }
+
+    #[test]
+    fn matches_eth_transfers_to_watched_addresses() {
+        let watched = watched();
+        let payment = native_payment(10, &transaction(INVOICE, 500), &watched).unwrap();
+        assert_eq!(payment.invoice_address, INVOICE.to_string());
+        assert_eq!(payment.sender, SENDER.to_string());
+        assert_eq!(payment.amount, U256::from(500));
+        assert_eq!(payment.block_number, 10);
+        assert_eq!(payment.log_index, None);
+
+        assert!(native_payment(10, &transaction(INVOICE, 0), &watched).is_none());
+        assert!(native_payment(10, &transaction(TOKEN_INVOICE, 500), &watched).is_none());
+        let creation = Transaction {
+            to: None,
+            ..transaction(INVOICE, 500)
+        };
+        assert!(native_payment(10, &creation, &watched).is_none());
+    }
+
+    #[test]
+    fn matches_token_transfers_of_the_expected_token() {
+        let watched = watched();
+        let payment = token_payment(10, &transfer_log(TOKEN, TOKEN_INVOICE, 42), &watched)
+            .unwrap()
+            .unwrap();
+        assert_eq!(payment.invoice_address, TOKEN_INVOICE.to_string());
+        assert_eq!(payment.sender, SENDER.to_string());
+        assert_eq!(payment.amount, U256::from(42));
+        assert_eq!(payment.log_index, Some(7));
+
+        let other_token = transfer_log(OTHER_TOKEN, TOKEN_INVOICE, 42);
+        assert!(token_payment(10, &other_token, &watched).unwrap().is_none());
+        let native_invoice = transfer_log(TOKEN, INVOICE, 42);
+        assert!(token_payment(10, &native_invoice, &watched)
+            .unwrap()
+            .is_none());
+    }
+
+    /// Indexed hashes of blocks `first..=last`, diverging from the canonical
+    /// chain after `fork`.
+    async fn ancestor(first: u64, last: u64, fork: u64) -> u64 {
+        let canonical = |number: u64| B256::from(U256::from(number));
+        common_ancestor(
+            last,
+            |number| async move {
+                Ok((first..=last).contains(&number).then(|| {
+                    if number > fork {
+                        B256::repeat_byte(0xff).to_string()
+                    } else {
+                        canonical(number).to_string()
+                    }
+                }))
+            },
+            |number| async move { Ok(canonical(number)) },
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn finds_the_newest_canonical_block() {
+        assert_eq!(ancestor(100, 200, 195).await, 195);
+        assert_eq!(ancestor(100, 200, 200).await, 200);
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_oldest_kept_block() {
+        // Only REORG_DEPTH hashes are kept, a deeper reorg stops below them.
+        let last = 1_000;
+        let first = last - REORG_DEPTH + 1;
+        assert_eq!(ancestor(first, last, 0).await, first - 1);
+        assert_eq!(ancestor(0, 10, 0).await, 0);
+    }
+}
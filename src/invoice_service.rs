@@ -1,122 +1,961 @@
+use crate::api_keys::{parse_scopes, ApiKey, Scope};
+use crate::crypto::KeyRing;
+use crate::hd_wallet::HdWallet;
 use crate::invoices::{InvoiceAction, InvoiceState};
+use crate::merchants::{Merchant, MerchantDefaults, MerchantScope};
+use crate::payments::{Payment, PendingPayment};
+use crate::rescans::{Rescan, RescanStatus};
+use crate::sweeps::{Sweep, SweepFees, SweepStatus, SweepTransaction, SweepTransactionKind};
+use crate::utils::{numeric_to_u256, u256_to_numeric};
+use alloy::primitives::U256;
+use bigdecimal::BigDecimal;
 use diesel::prelude::*;
-use eyre::Result;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::sql_types::Text;
+use eyre::{eyre, Result};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
+type ApiKeyModel = crate::models::ApiKey;
 type InvoiceModel = crate::models::Invoice;
+type MerchantModel = crate::models::Merchant;
+type PaymentModel = crate::models::Payment;
+type IndexedBlockModel = crate::models::IndexedBlock;
+type RescanModel = crate::models::Rescan;
+type SweepModel = crate::models::Sweep;
+type SweepTransactionModel = crate::models::SweepTransaction;
 type Invoice = crate::invoices::Invoice;
 
+define_sql_function! { fn nextval(sequence: Text) -> BigInt; }
+
+pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+
+/// Database access over a connection pool. Clones share the pool, so the
+/// HTTP handlers and the invoice loop query the database concurrently.
+#[derive(Clone)]
 pub struct InvoiceService {
-    connection: PgConnection,
+    pool: DbPool,
+    hd_wallet: Arc<HdWallet>,
+    key_ring: Option<Arc<KeyRing>>,
 }
 impl InvoiceService {
-    pub fn new(connection: PgConnection) -> Self {
-        Self { connection }
+    pub fn new(pool: DbPool, hd_wallet: Arc<HdWallet>, key_ring: Option<KeyRing>) -> Self {
+        Self {
+            pool,
+            hd_wallet,
+            key_ring: key_ring.map(Arc::new),
+        }
     }
 
-    pub fn pending_invoices(&mut self) -> Result<Vec<Invoice>> {
+    fn connection(&self) -> Result<PooledConnection<ConnectionManager<PgConnection>>> {
+        Ok(self.pool.get()?)
+    }
+
+    pub fn pending_invoices(&self) -> Result<Vec<Invoice>> {
         use crate::schema::invoice::dsl::*;
 
-        Ok((invoice
+        (invoice
             .filter(
                 state
                     .ne(InvoiceState::Rejected.to_int() as i32)
-                    .and(state.ne(InvoiceState::Sent.to_int() as i32)),
+                    .and(state.ne(InvoiceState::Sent.to_int() as i32))
+                    .and(state.ne(InvoiceState::Expired.to_int() as i32))
+                    .and(state.ne(InvoiceState::SweepCancelled.to_int() as i32)),
             )
             .select(InvoiceModel::as_select())
-            .load(&mut self.connection)? as Vec<InvoiceModel>)
+            .load(&mut self.connection()?)? as Vec<InvoiceModel>)
             .into_iter()
-            .map(|invoice_model: InvoiceModel| Self::model_to_invoice(invoice_model))
-            .collect())
+            .map(|invoice_model: InvoiceModel| self.model_to_invoice(invoice_model))
+            .collect()
     }
 
-    pub fn get_invoices_by_state(&mut self, invoice_state: InvoiceState) -> Result<Vec<Invoice>> {
+    /// Invoices whose funds still have to be sent to the receiver, or whose
+    /// sweep has not been confirmed yet.
+    pub fn queued_sweeps(&self) -> Result<Vec<Invoice>> {
         use crate::schema::invoice::dsl::*;
 
         let invoices = invoice
+            .filter(
+                state
+                    .eq_any([
+                        InvoiceState::Complete.to_int() as i32,
+                        InvoiceState::Sweeping.to_int() as i32,
+                        InvoiceState::SweepFailed.to_int() as i32,
+                        InvoiceState::SweepDeferred.to_int() as i32,
+                    ])
+                    .and(complete_action.eq(InvoiceAction::SendToReceiver.to_int() as i32)),
+            )
+            .select(InvoiceModel::as_select())
+            .load(&mut self.connection()?)?
+            .into_iter()
+            .map(|invoice_model: InvoiceModel| self.model_to_invoice(invoice_model))
+            .collect::<Result<_>>()?;
+
+        Ok(invoices)
+    }
+
+    pub fn get_invoices_by_state(
+        &self,
+        scope: MerchantScope,
+        invoice_state: InvoiceState,
+    ) -> Result<Vec<Invoice>> {
+        use crate::schema::invoice::dsl::*;
+
+        let mut query = invoice
             .filter(state.eq(invoice_state.to_int() as i32))
             .select(InvoiceModel::as_select())
-            .load(&mut self.connection)?
+            .into_boxed();
+        if let MerchantScope::Merchant(owner) = scope {
+            query = query.filter(merchant_id.eq(owner));
+        }
+        let invoices = query
+            .load(&mut self.connection()?)?
             .into_iter()
-            .map(|invoice_model: InvoiceModel| Self::model_to_invoice(invoice_model))
-            .collect();
+            .map(|invoice_model: InvoiceModel| self.model_to_invoice(invoice_model))
+            .collect::<Result<_>>()?;
 
         Ok(invoices)
     }
 
     pub fn get_invoices_by_action(
-        &mut self,
+        &self,
+        scope: MerchantScope,
         invoice_action: InvoiceAction,
     ) -> Result<Vec<Invoice>> {
         use crate::schema::invoice::dsl::*;
 
-        let invoices = invoice
+        let mut query = invoice
             .filter(complete_action.eq(invoice_action.to_int() as i32))
             .select(InvoiceModel::as_select())
-            .load(&mut self.connection)?
+            .into_boxed();
+        if let MerchantScope::Merchant(owner) = scope {
+            query = query.filter(merchant_id.eq(owner));
+        }
+        let invoices = query
+            .load(&mut self.connection()?)?
             .into_iter()
-            .map(|invoice_model: InvoiceModel| Self::model_to_invoice(invoice_model))
-            .collect();
+            .map(|invoice_model: InvoiceModel| self.model_to_invoice(invoice_model))
+            .collect::<Result<_>>()?;
 
         Ok(invoices)
     }
 
-    pub fn get_invoice_by_address(&mut self, invoice_address: String) -> Result<Invoice> {
+    /// Fails as not found for invoices of other merchants.
+    pub fn get_invoice_by_address(
+        &self,
+        scope: MerchantScope,
+        invoice_address: String,
+    ) -> Result<Invoice> {
         use crate::schema::invoice::dsl::*;
 
-        let query_result = invoice
+        let mut query = invoice
             .filter(address.eq(invoice_address))
             .select(InvoiceModel::as_select())
-            .first(&mut self.connection)?;
-        Ok(Self::model_to_invoice(query_result))
-    }
-
-    fn model_to_invoice(model: InvoiceModel) -> Invoice {
-        Invoice::load(
-            model.mnemonic,
-            model.receiver,
-            model.value,
-            model.state as u32,
-            model.lifetime as u64,
-            model.complete_action as u32,
-        )
+            .into_boxed();
+        if let MerchantScope::Merchant(owner) = scope {
+            query = query.filter(merchant_id.eq(owner));
+        }
+        let query_result = query.first(&mut self.connection()?)?;
+        self.model_to_invoice(query_result)
+    }
+
+    fn model_to_invoice(&self, mut model: InvoiceModel) -> Result<Invoice> {
+        // Watch-only servers never rebuild signers, so they need no keys.
+        if !self.hd_wallet.is_watch_only() {
+            model.mnemonic = model
+                .mnemonic
+                .map(|mnemonic| self.decrypt_mnemonic(&mnemonic, &model.address))
+                .transpose()?;
+        }
+        Invoice::load(model, &self.hd_wallet)
+    }
+
+    fn decrypt_mnemonic(&self, stored: &str, invoice_address: &str) -> Result<String> {
+        match &self.key_ring {
+            Some(key_ring) => key_ring.decrypt(stored, invoice_address),
+            None if KeyRing::is_encrypted(stored) => Err(eyre!(
+                "Mnemonic of {} is encrypted but no encryption keys are configured",
+                invoice_address
+            )),
+            None => Ok(stored.to_string()),
+        }
+    }
+
+    fn encrypt_mnemonic(&self, mnemonic: &str, invoice_address: &str) -> Result<String> {
+        match &self.key_ring {
+            Some(key_ring) => key_ring.encrypt(mnemonic, invoice_address),
+            None => Ok(mnemonic.to_string()),
+        }
+    }
+
+    /// Encrypts every plaintext mnemonic and re-encrypts the ones sealed with
+    /// an older key version. Returns the number of updated rows.
+    pub fn encrypt_mnemonics(&self) -> Result<usize> {
+        use crate::schema::invoice::dsl::*;
+
+        let key_ring = self
+            .key_ring
+            .as_ref()
+            .ok_or_else(|| eyre!("No encryption keys configured"))?;
+
+        self.connection()?.transaction(|connection| {
+            let rows = invoice
+                .filter(mnemonic.is_not_null())
+                .select((address, mnemonic))
+                .for_update()
+                .load::<(String, Option<String>)>(connection)?;
+
+            let mut updated = 0;
+            for (invoice_address, stored) in rows {
+                let Some(stored) = stored else { continue };
+                if key_ring.is_current(&stored) {
+                    continue;
+                }
+                let encrypted = key_ring.encrypt(
+                    &key_ring.decrypt(&stored, &invoice_address)?,
+                    &invoice_address,
+                )?;
+                diesel::update(invoice.find(&invoice_address))
+                    .set(mnemonic.eq(encrypted))
+                    .execute(connection)?;
+                updated += 1;
+            }
+            Ok(updated)
+        })
+    }
+
+    /// Reserves the BIP-44 address index for a new invoice wallet.
+    pub fn next_derivation_index(&self) -> Result<u32> {
+        let index = diesel::select(nextval("invoice_derivation_index_seq"))
+            .get_result::<i64>(&mut self.connection()?)?;
+        Ok(index as u32)
     }
 
-    pub fn create_invoice(&mut self, invoice_struct: Invoice) -> Result<Invoice> {
+    pub fn create_invoice(&self, invoice_struct: Invoice) -> Result<Invoice> {
         use crate::schema::invoice;
 
-        let new_invoice = Self::invoice_to_new_record(invoice_struct);
-        Ok(Self::model_to_invoice(
-            diesel::insert_into(invoice::table)
-                .values(&new_invoice)
-                .returning(InvoiceModel::as_returning())
-                .get_result(&mut self.connection)?,
-        ))
+        let new_invoice = self.invoice_to_new_record(invoice_struct)?;
+        let query_result = diesel::insert_into(invoice::table)
+            .values(&new_invoice)
+            .returning(InvoiceModel::as_returning())
+            .get_result(&mut self.connection()?)?;
+        self.model_to_invoice(query_result)
     }
 
-    fn invoice_to_new_record(invoice_struct: Invoice) -> InvoiceModel {
-        InvoiceModel {
+    fn invoice_to_new_record(&self, invoice_struct: Invoice) -> Result<InvoiceModel> {
+        Ok(InvoiceModel {
+            mnemonic: invoice_struct
+                .mnemonic
+                .map(|mnemonic| self.encrypt_mnemonic(&mnemonic, &invoice_struct.address))
+                .transpose()?,
             address: invoice_struct.address.clone(),
             receiver: invoice_struct.receiver,
-            mnemonic: invoice_struct.mnemonic,
             state: invoice_struct.state.to_int() as i32,
-            value: invoice_struct.value,
+            value: u256_to_numeric(invoice_struct.value),
             lifetime: invoice_struct.lifetime as i32,
             complete_action: invoice_struct.complete_action.to_int() as i32,
-        }
+            confirmations: invoice_struct.confirmations as i32,
+            scanned_block: invoice_struct.scanned_block as i64,
+            token: invoice_struct.token,
+            decimals: invoice_struct.decimals as i32,
+            top_up_cost: u256_to_numeric(invoice_struct.top_up_cost),
+            derivation_index: invoice_struct.derivation_index.map(|index| index as i32),
+            pending_tx_hash: invoice_struct
+                .pending_payment
+                .as_ref()
+                .map(|payment| payment.tx_hash.clone()),
+            pending_amount: invoice_struct
+                .pending_payment
+                .map(|payment| u256_to_numeric(payment.amount)),
+            merchant_id: invoice_struct.merchant_id,
+            webhook_url: invoice_struct.webhook_url,
+            fiat_amount: invoice_struct.fiat.as_ref().map(|fiat| fiat.amount.clone()),
+            fiat_currency: invoice_struct
+                .fiat
+                .as_ref()
+                .map(|fiat| fiat.currency.clone()),
+            exchange_rate: invoice_struct.fiat.as_ref().map(|fiat| fiat.rate.clone()),
+            rate_timestamp: invoice_struct.fiat.map(|fiat| fiat.timestamp as i64),
+        })
     }
 
+    /// Updates the state together with the pending payment that is shown
+    /// while the invoice is PaymentPending.
     pub fn update_invoice_state(
-        &mut self,
+        &self,
         invoice_address: String,
         invoice_state: InvoiceState,
+        pending_payment: Option<&PendingPayment>,
     ) -> Result<Invoice> {
         use crate::schema::invoice::dsl::*;
 
-        Ok(Self::model_to_invoice(
-            diesel::update(invoice.find(invoice_address))
-                .set(state.eq(invoice_state.to_int() as i32))
-                .returning(InvoiceModel::as_returning())
-                .get_result(&mut self.connection)?,
-        ))
+        let query_result = diesel::update(invoice.find(invoice_address))
+            .set((
+                state.eq(invoice_state.to_int() as i32),
+                pending_tx_hash.eq(pending_payment.map(|payment| payment.tx_hash.clone())),
+                pending_amount.eq(pending_payment.map(|payment| u256_to_numeric(payment.amount))),
+            ))
+            .returning(InvoiceModel::as_returning())
+            .get_result(&mut self.connection()?)?;
+        self.model_to_invoice(query_result)
+    }
+
+    /// Stores the states and pending payments of the invoices in one
+    /// transaction. An invoice is only updated while it is still in the state
+    /// it was read in, given in `previous_states`; the addresses of invoices
+    /// changed in the meantime are returned and left untouched.
+    pub fn update_invoice_states(
+        &self,
+        invoices: &[Invoice],
+        previous_states: &[InvoiceState],
+    ) -> Result<HashSet<String>> {
+        use crate::schema::invoice::dsl::*;
+
+        self.connection()?.transaction(|connection| {
+            let mut changed = HashSet::new();
+            for (invoice_struct, previous_state) in invoices.iter().zip(previous_states) {
+                let pending_payment = invoice_struct.pending_payment.as_ref();
+                let updated = diesel::update(
+                    invoice
+                        .find(&invoice_struct.address)
+                        .filter(state.eq(previous_state.to_int() as i32)),
+                )
+                .set((
+                    state.eq(invoice_struct.state.to_int() as i32),
+                    pending_tx_hash.eq(pending_payment.map(|payment| payment.tx_hash.clone())),
+                    pending_amount
+                        .eq(pending_payment.map(|payment| u256_to_numeric(payment.amount))),
+                ))
+                .execute(connection)?;
+                if updated == 0 {
+                    changed.insert(invoice_struct.address.clone());
+                }
+            }
+            Ok(changed)
+        })
+    }
+
+    pub fn add_top_up_cost(&self, invoice_address: String, cost: U256) -> Result<Invoice> {
+        use crate::schema::invoice::dsl::*;
+
+        let query_result = diesel::update(invoice.find(invoice_address))
+            .set(top_up_cost.eq(top_up_cost + u256_to_numeric(cost)))
+            .returning(InvoiceModel::as_returning())
+            .get_result(&mut self.connection()?)?;
+        self.model_to_invoice(query_result)
+    }
+
+    /// Addresses and tokens of invoices still waiting for payment.
+    pub fn watched_addresses(&self) -> Result<Vec<(String, Option<String>)>> {
+        use crate::schema::invoice::dsl::*;
+
+        Ok(invoice
+            .filter(state.eq_any([
+                InvoiceState::Empty.to_int() as i32,
+                InvoiceState::Incomplete.to_int() as i32,
+                InvoiceState::Confirming.to_int() as i32,
+                InvoiceState::PaymentPending.to_int() as i32,
+            ]))
+            .select((address, token))
+            .load(&mut self.connection()?)?)
+    }
+
+    /// Addresses and tokens of all invoices.
+    pub fn invoice_addresses(&self) -> Result<Vec<(String, Option<String>)>> {
+        use crate::schema::invoice::dsl::*;
+
+        Ok(invoice
+            .select((address, token))
+            .load(&mut self.connection()?)?)
+    }
+
+    /// Earliest block the watched invoices were created at, where the
+    /// indexer starts when it has no cursor yet.
+    /// Invoices created before payment indexing existed have `scanned_block`
+    /// 0, which is ignored so the indexer does not start from genesis.
+    pub fn earliest_watched_block(&self) -> Result<Option<u64>> {
+        use crate::schema::invoice::dsl::*;
+
+        let block: Option<i64> = invoice
+            .filter(scanned_block.gt(0))
+            .filter(state.eq_any([
+                InvoiceState::Empty.to_int() as i32,
+                InvoiceState::Incomplete.to_int() as i32,
+                InvoiceState::Confirming.to_int() as i32,
+                InvoiceState::PaymentPending.to_int() as i32,
+            ]))
+            .select(diesel::dsl::min(scanned_block))
+            .first(&mut self.connection()?)?;
+        Ok(block.map(|block| block as u64))
+    }
+
+    pub fn indexer_cursor(&self) -> Result<Option<u64>> {
+        use crate::schema::indexer_cursor::dsl::*;
+
+        let block: Option<i64> = indexer_cursor
+            .select(last_scanned_block)
+            .first(&mut self.connection()?)
+            .optional()?;
+        Ok(block.map(|block| block as u64))
+    }
+
+    pub fn indexed_block_hash(&self, block_number: u64) -> Result<Option<String>> {
+        use crate::schema::indexed_blocks::dsl::*;
+
+        Ok(indexed_blocks
+            .find(block_number as i64)
+            .select(hash)
+            .first(&mut self.connection()?)
+            .optional()?)
+    }
+
+    /// Stores the payments of an indexed block and moves the indexer cursor
+    /// to it. Only the last `blocks_kept` block hashes are kept.
+    pub fn record_indexed_block(
+        &self,
+        block_number: u64,
+        block_hash: String,
+        new_payments: Vec<Payment>,
+        blocks_kept: u64,
+    ) -> Result<()> {
+        use crate::schema::{indexed_blocks, indexer_cursor, payments};
+
+        let records: Vec<PaymentModel> = new_payments
+            .into_iter()
+            .map(Self::payment_to_new_record)
+            .collect();
+        let block = IndexedBlockModel {
+            number: block_number as i64,
+            hash: block_hash,
+        };
+
+        self.connection()?.transaction(|connection| {
+            diesel::insert_into(payments::table)
+                .values(&records)
+                .on_conflict_do_nothing()
+                .execute(connection)?;
+            diesel::insert_into(indexed_blocks::table)
+                .values(&block)
+                .on_conflict(indexed_blocks::number)
+                .do_update()
+                .set(indexed_blocks::hash.eq(&block.hash))
+                .execute(connection)?;
+            diesel::delete(indexed_blocks::table.filter(
+                indexed_blocks::number.lt(block_number.saturating_sub(blocks_kept) as i64),
+            ))
+            .execute(connection)?;
+            diesel::insert_into(indexer_cursor::table)
+                .values((
+                    indexer_cursor::id.eq(1),
+                    indexer_cursor::last_scanned_block.eq(block_number as i64),
+                ))
+                .on_conflict(indexer_cursor::id)
+                .do_update()
+                .set(indexer_cursor::last_scanned_block.eq(block_number as i64))
+                .execute(connection)?;
+            Ok(())
+        })
+    }
+
+    /// Drops everything indexed after `ancestor`, the last block shared with
+    /// the canonical chain after a reorg.
+    pub fn rollback_index(&self, ancestor: u64) -> Result<()> {
+        use crate::schema::{indexed_blocks, indexer_cursor, invoice, payments};
+
+        let ancestor = ancestor as i64;
+        self.connection()?.transaction(|connection| {
+            diesel::delete(payments::table.filter(payments::block_number.gt(ancestor)))
+                .execute(connection)?;
+            diesel::delete(indexed_blocks::table.filter(indexed_blocks::number.gt(ancestor)))
+                .execute(connection)?;
+            diesel::update(indexer_cursor::table)
+                .set(indexer_cursor::last_scanned_block.eq(ancestor))
+                .execute(connection)?;
+            diesel::update(invoice::table.filter(invoice::scanned_block.gt(ancestor)))
+                .set(invoice::scanned_block.eq(ancestor))
+                .execute(connection)?;
+            Ok(())
+        })
+    }
+
+    pub fn get_payments(&self, payment_invoice_address: String) -> Result<Vec<Payment>> {
+        use crate::schema::payments::dsl::*;
+
+        payments
+            .filter(invoice_address.eq(payment_invoice_address))
+            .order((block_number, log_index))
+            .select(PaymentModel::as_select())
+            .load(&mut self.connection()?)?
+            .into_iter()
+            .map(Self::model_to_payment)
+            .collect()
+    }
+
+    /// Payments of the invoices, by invoice address.
+    pub fn get_payments_by_invoice(
+        &self,
+        invoice_addresses: Vec<String>,
+    ) -> Result<HashMap<String, Vec<Payment>>> {
+        use crate::schema::payments::dsl::*;
+
+        let mut by_invoice: HashMap<String, Vec<Payment>> = HashMap::new();
+        for model in payments
+            .filter(invoice_address.eq_any(invoice_addresses))
+            .order((block_number, log_index))
+            .select(PaymentModel::as_select())
+            .load(&mut self.connection()?)?
+        {
+            let payment = Self::model_to_payment(model)?;
+            by_invoice
+                .entry(payment.invoice_address.clone())
+                .or_default()
+                .push(payment);
+        }
+        Ok(by_invoice)
+    }
+
+    fn model_to_payment(model: PaymentModel) -> Result<Payment> {
+        Ok(Payment {
+            invoice_address: model.invoice_address,
+            tx_hash: model.tx_hash,
+            block_number: model.block_number as u64,
+            sender: model.sender,
+            amount: numeric_to_u256(model.amount)?,
+            log_index: model.log_index.map(|index| index as u64),
+        })
+    }
+
+    fn payment_to_new_record(payment: Payment) -> PaymentModel {
+        PaymentModel {
+            invoice_address: payment.invoice_address,
+            tx_hash: payment.tx_hash,
+            block_number: payment.block_number as i64,
+            sender: payment.sender,
+            amount: u256_to_numeric(payment.amount),
+            log_index: payment.log_index.map(|index| index as i32),
+        }
+    }
+
+    pub fn get_sweep(&self, sweep_invoice_address: String) -> Result<Option<Sweep>> {
+        use crate::schema::sweeps::dsl::*;
+
+        sweeps
+            .filter(invoice_address.eq(sweep_invoice_address))
+            .select(SweepModel::as_select())
+            .first(&mut self.connection()?)
+            .optional()?
+            .map(Self::model_to_sweep)
+            .transpose()
+    }
+
+    /// Stores the sweep together with the invoice state it leads to and the
+    /// transaction it has just broadcast, if any.
+    pub fn save_sweep(
+        &self,
+        sweep: &Sweep,
+        invoice_state: InvoiceState,
+        transaction: Option<&SweepTransaction>,
+    ) -> Result<()> {
+        use crate::schema::{invoice, sweep_transactions, sweeps};
+
+        let record = Self::sweep_to_record(sweep);
+        let transaction_record =
+            transaction.map(|transaction| Self::sweep_transaction_to_record(sweep, transaction));
+        self.connection()?.transaction(|connection| {
+            diesel::insert_into(sweeps::table)
+                .values(&record)
+                .on_conflict(sweeps::invoice_address)
+                .do_update()
+                .set(&record)
+                .execute(connection)?;
+            if let Some(transaction_record) = &transaction_record {
+                diesel::insert_into(sweep_transactions::table)
+                    .values(transaction_record)
+                    .execute(connection)?;
+            }
+            diesel::update(invoice::table.find(&sweep.invoice_address))
+                .set(invoice::state.eq(invoice_state.to_int() as i32))
+                .execute(connection)?;
+            Ok(())
+        })
+    }
+
+    /// Flags a pending sweep to be cancelled by the process holding the keys.
+    /// Returns false when the invoice has no pending sweep.
+    pub fn request_sweep_cancel(&self, sweep_invoice_address: String) -> Result<bool> {
+        use crate::schema::sweeps::dsl::*;
+
+        let updated = diesel::update(
+            sweeps.filter(
+                invoice_address
+                    .eq(sweep_invoice_address)
+                    .and(status.eq(SweepStatus::Pending.to_int() as i32)),
+            ),
+        )
+        .set(cancel_requested.eq(true))
+        .execute(&mut self.connection()?)?;
+        Ok(updated > 0)
+    }
+
+    /// Transactions broadcast with the sweep nonce, newest first.
+    pub fn get_sweep_transactions(
+        &self,
+        sweep_invoice_address: String,
+        sweep_nonce: u64,
+    ) -> Result<Vec<SweepTransaction>> {
+        use crate::schema::sweep_transactions::dsl::*;
+
+        sweep_transactions
+            .filter(
+                invoice_address
+                    .eq(sweep_invoice_address)
+                    .and(nonce.eq(sweep_nonce as i64)),
+            )
+            .order(id.desc())
+            .select(SweepTransactionModel::as_select())
+            .load(&mut self.connection()?)?
+            .into_iter()
+            .map(Self::model_to_sweep_transaction)
+            .collect()
+    }
+
+    fn model_to_sweep(model: SweepModel) -> Result<Sweep> {
+        let to_fee = |fee: BigDecimal| -> Result<u128> { Ok(numeric_to_u256(fee)?.to()) };
+        Ok(Sweep {
+            invoice_address: model.invoice_address,
+            tx_hash: model.tx_hash,
+            nonce: model.nonce.map(|nonce| nonce as u64),
+            max_fee_per_gas: model.max_fee_per_gas.map(to_fee).transpose()?,
+            max_priority_fee_per_gas: model.max_priority_fee_per_gas.map(to_fee).transpose()?,
+            status: SweepStatus::from_int(model.status as u32),
+            attempts: model.attempts as u32,
+            last_error: model.last_error,
+            next_attempt_at: model.next_attempt_at as u64,
+            updated_at: model.updated_at as u64,
+            submitted_at: model.submitted_at as u64,
+            cancel_requested: model.cancel_requested,
+            deadline: model.deadline.map(|deadline| deadline as u64),
+            alerted: model.alerted,
+            top_up_tx_hash: model.top_up_tx_hash,
+        })
+    }
+
+    fn sweep_to_record(sweep: &Sweep) -> SweepModel {
+        let to_numeric = |fee: u128| u256_to_numeric(U256::from(fee));
+        SweepModel {
+            invoice_address: sweep.invoice_address.clone(),
+            tx_hash: sweep.tx_hash.clone(),
+            nonce: sweep.nonce.map(|nonce| nonce as i64),
+            max_fee_per_gas: sweep.max_fee_per_gas.map(to_numeric),
+            max_priority_fee_per_gas: sweep.max_priority_fee_per_gas.map(to_numeric),
+            status: sweep.status.to_int() as i32,
+            attempts: sweep.attempts as i32,
+            last_error: sweep.last_error.clone(),
+            next_attempt_at: sweep.next_attempt_at as i64,
+            updated_at: sweep.updated_at as i64,
+            submitted_at: sweep.submitted_at as i64,
+            cancel_requested: sweep.cancel_requested,
+            deadline: sweep.deadline.map(|deadline| deadline as i64),
+            alerted: sweep.alerted,
+            top_up_tx_hash: sweep.top_up_tx_hash.clone(),
+        }
+    }
+
+    fn model_to_sweep_transaction(model: SweepTransactionModel) -> Result<SweepTransaction> {
+        Ok(SweepTransaction {
+            tx_hash: model.tx_hash.parse()?,
+            nonce: model.nonce as u64,
+            fees: SweepFees {
+                max_fee_per_gas: numeric_to_u256(model.max_fee_per_gas)?.to(),
+                max_priority_fee_per_gas: numeric_to_u256(model.max_priority_fee_per_gas)?.to(),
+            },
+            kind: SweepTransactionKind::from_int(model.kind as u32),
+        })
+    }
+
+    fn sweep_transaction_to_record(
+        sweep: &Sweep,
+        transaction: &SweepTransaction,
+    ) -> SweepTransactionModel {
+        SweepTransactionModel {
+            invoice_address: sweep.invoice_address.clone(),
+            tx_hash: transaction.tx_hash.to_string(),
+            nonce: transaction.nonce as i64,
+            max_fee_per_gas: u256_to_numeric(U256::from(transaction.fees.max_fee_per_gas)),
+            max_priority_fee_per_gas: u256_to_numeric(U256::from(
+                transaction.fees.max_priority_fee_per_gas,
+            )),
+            kind: transaction.kind.to_int() as i32,
+            created_at: sweep.submitted_at as i64,
+        }
+    }
+
+    /// Stores payments that are not recorded yet and returns them.
+    pub fn insert_payments(&self, new_payments: Vec<Payment>) -> Result<Vec<Payment>> {
+        use crate::schema::payments;
+
+        let records: Vec<PaymentModel> = new_payments
+            .into_iter()
+            .map(Self::payment_to_new_record)
+            .collect();
+        diesel::insert_into(payments::table)
+            .values(&records)
+            .on_conflict_do_nothing()
+            .returning(PaymentModel::as_returning())
+            .get_results(&mut self.connection()?)?
+            .into_iter()
+            .map(Self::model_to_payment)
+            .collect()
+    }
+
+    pub fn create_rescan(&self, rescan_from_block: u64, rescan_to_block: u64) -> Result<Rescan> {
+        use crate::schema::rescans::dsl::*;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)?
+            .as_secs() as i64;
+        let query_result = diesel::insert_into(rescans)
+            .values((
+                from_block.eq(rescan_from_block as i64),
+                to_block.eq(rescan_to_block as i64),
+                next_block.eq(rescan_from_block as i64),
+                status.eq(RescanStatus::Running.to_int() as i32),
+                missed_payments.eq(0),
+                repaired_invoices.eq(0),
+                created_at.eq(now),
+                updated_at.eq(now),
+            ))
+            .returning(RescanModel::as_returning())
+            .get_result(&mut self.connection()?)?;
+        Ok(Self::model_to_rescan(query_result))
+    }
+
+    pub fn get_rescan(&self, rescan_id: i32) -> Result<Rescan> {
+        use crate::schema::rescans::dsl::*;
+
+        let query_result = rescans
+            .find(rescan_id)
+            .select(RescanModel::as_select())
+            .first(&mut self.connection()?)?;
+        Ok(Self::model_to_rescan(query_result))
+    }
+
+    /// Oldest rescan that has not finished yet.
+    pub fn next_rescan(&self) -> Result<Option<Rescan>> {
+        use crate::schema::rescans::dsl::*;
+
+        Ok(rescans
+            .filter(status.eq(RescanStatus::Running.to_int() as i32))
+            .order(id)
+            .select(RescanModel::as_select())
+            .first(&mut self.connection()?)
+            .optional()?
+            .map(Self::model_to_rescan))
+    }
+
+    pub fn save_rescan(&self, rescan: &Rescan) -> Result<()> {
+        use crate::schema::rescans::dsl::*;
+
+        diesel::update(rescans.find(rescan.id))
+            .set(&Self::rescan_to_record(rescan))
+            .execute(&mut self.connection()?)?;
+        Ok(())
+    }
+
+    fn model_to_rescan(model: RescanModel) -> Rescan {
+        Rescan {
+            id: model.id,
+            from_block: model.from_block as u64,
+            to_block: model.to_block as u64,
+            next_block: model.next_block as u64,
+            status: RescanStatus::from_int(model.status as u32),
+            missed_payments: model.missed_payments as u32,
+            repaired_invoices: model.repaired_invoices as u32,
+            created_at: model.created_at as u64,
+            updated_at: model.updated_at as u64,
+        }
+    }
+
+    fn rescan_to_record(rescan: &Rescan) -> RescanModel {
+        RescanModel {
+            id: rescan.id,
+            from_block: rescan.from_block as i64,
+            to_block: rescan.to_block as i64,
+            next_block: rescan.next_block as i64,
+            status: rescan.status.to_int() as i32,
+            missed_payments: rescan.missed_payments as i32,
+            repaired_invoices: rescan.repaired_invoices as i32,
+            created_at: rescan.created_at as i64,
+            updated_at: rescan.updated_at as i64,
+        }
+    }
+
+    pub fn create_api_key(
+        &self,
+        key_name: String,
+        key_scopes: &[Scope],
+        key_merchant_id: Option<i32>,
+        hash: String,
+    ) -> Result<ApiKey> {
+        use crate::schema::api_keys::dsl::*;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)?
+            .as_secs() as i64;
+        let query_result = diesel::insert_into(api_keys)
+            .values((
+                name.eq(key_name),
+                key_hash.eq(hash),
+                scopes.eq(key_scopes
+                    .iter()
+                    .map(|scope| scope.as_str().to_string())
+                    .collect::<Vec<_>>()),
+                created_at.eq(now),
+                merchant_id.eq(key_merchant_id),
+            ))
+            .returning(ApiKeyModel::as_returning())
+            .get_result(&mut self.connection()?)?;
+        Self::model_to_api_key(query_result)
+    }
+
+    pub fn list_api_keys(&self) -> Result<Vec<ApiKey>> {
+        use crate::schema::api_keys::dsl::*;
+
+        api_keys
+            .order(id)
+            .select(ApiKeyModel::as_select())
+            .load(&mut self.connection()?)?
+            .into_iter()
+            .map(Self::model_to_api_key)
+            .collect()
+    }
+
+    /// Active key with the given hash.
+    pub fn find_api_key(&self, hash: &str) -> Result<Option<ApiKey>> {
+        use crate::schema::api_keys::dsl::*;
+
+        api_keys
+            .filter(key_hash.eq(hash))
+            .filter(revoked_at.is_null())
+            .select(ApiKeyModel::as_select())
+            .first(&mut self.connection()?)
+            .optional()?
+            .map(Self::model_to_api_key)
+            .transpose()
+    }
+
+    /// Returns false when there is no active key with the id.
+    pub fn revoke_api_key(&self, key_id: i32) -> Result<bool> {
+        use crate::schema::api_keys::dsl::*;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)?
+            .as_secs() as i64;
+        let updated = diesel::update(api_keys.find(key_id).filter(revoked_at.is_null()))
+            .set(revoked_at.eq(now))
+            .execute(&mut self.connection()?)?;
+        Ok(updated > 0)
+    }
+
+    fn model_to_api_key(model: ApiKeyModel) -> Result<ApiKey> {
+        Ok(ApiKey {
+            id: model.id,
+            name: model.name,
+            scopes: parse_scopes(&model.scopes)?,
+            created_at: model.created_at as u64,
+            revoked_at: model.revoked_at.map(|revoked_at| revoked_at as u64),
+            merchant_id: model.merchant_id,
+        })
+    }
+
+    pub fn create_merchant(
+        &self,
+        merchant_name: String,
+        defaults: &MerchantDefaults,
+    ) -> Result<Merchant> {
+        use crate::schema::merchants::dsl::*;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)?
+            .as_secs() as i64;
+        let query_result = diesel::insert_into(merchants)
+            .values((
+                name.eq(merchant_name),
+                receiver.eq(defaults.receiver.clone()),
+                lifetime.eq(defaults.lifetime.map(|seconds| seconds as i32)),
+                complete_action.eq(defaults
+                    .complete_action
+                    .as_ref()
+                    .map(|action| action.to_int() as i32)),
+                webhook_url.eq(defaults.webhook_url.clone()),
+                created_at.eq(now),
+            ))
+            .returning(MerchantModel::as_returning())
+            .get_result(&mut self.connection()?)?;
+        Ok(Self::model_to_merchant(query_result))
+    }
+
+    pub fn list_merchants(&self) -> Result<Vec<Merchant>> {
+        use crate::schema::merchants::dsl::*;
+
+        Ok(merchants
+            .order(id)
+            .select(MerchantModel::as_select())
+            .load(&mut self.connection()?)?
+            .into_iter()
+            .map(Self::model_to_merchant)
+            .collect())
+    }
+
+    pub fn get_merchant(&self, merchant_id: i32) -> Result<Option<Merchant>> {
+        use crate::schema::merchants::dsl::*;
+
+        Ok(merchants
+            .find(merchant_id)
+            .select(MerchantModel::as_select())
+            .first(&mut self.connection()?)
+            .optional()?
+            .map(Self::model_to_merchant))
+    }
+
+    /// Replaces all defaults of the merchant. Returns None when it does not
+    /// exist.
+    pub fn update_merchant_defaults(
+        &self,
+        merchant_id: i32,
+        defaults: &MerchantDefaults,
+    ) -> Result<Option<Merchant>> {
+        use crate::schema::merchants::dsl::*;
+
+        Ok(diesel::update(merchants.find(merchant_id))
+            .set((
+                receiver.eq(defaults.receiver.clone()),
+                lifetime.eq(defaults.lifetime.map(|seconds| seconds as i32)),
+                complete_action.eq(defaults
+                    .complete_action
+                    .as_ref()
+                    .map(|action| action.to_int() as i32)),
+                webhook_url.eq(defaults.webhook_url.clone()),
+            ))
+            .returning(MerchantModel::as_returning())
+            .get_result(&mut self.connection()?)
+            .optional()?
+            .map(Self::model_to_merchant))
+    }
+
+    fn model_to_merchant(model: MerchantModel) -> Merchant {
+        Merchant {
+            id: model.id,
+            name: model.name,
+            defaults: MerchantDefaults {
+                receiver: model.receiver,
+                lifetime: model.lifetime.map(|seconds| seconds as u64),
+                complete_action: model
+                    .complete_action
+                    .map(|action| InvoiceAction::from_int(action as u32)),
+                webhook_url: model.webhook_url,
+            },
+            created_at: model.created_at as u64,
+        }
     }
 }
@@ -1,18 +1,38 @@
+use crate::balances::{self, BalanceQuery};
+use crate::erc20;
+use crate::fees::{self, Urgency};
+use crate::funding::FundingStation;
+use crate::hd_wallet::HdWallet;
+use crate::heads;
+use crate::indexer::{self, ScannedBlock, WatchedAddresses};
 use crate::invoice_service::InvoiceService;
-use crate::utils::wei_to_eth;
+use crate::mempool;
+use crate::merchants::MerchantScope;
+use crate::payments::{Payment, PendingPayment};
+use crate::prices::{FiatQuote, PriceSource};
+use crate::quorum::Quorum;
+use crate::rescans::{Rescan, RescanStatus};
+use crate::rpc::FailoverProvider;
+use crate::sweeps::{
+    Sweep, SweepFees, SweepSettings, SweepTooExpensive, SweepTransaction, SweepTransactionKind,
+    MAX_SWEEP_ATTEMPTS,
+};
+use crate::utils::{confirmations, confirmed_block, numeric_to_u256, parse_amount, InvalidRequest};
+use alloy::eips::BlockId;
 use alloy::network::{EthereumWallet, TransactionBuilder};
-use alloy::primitives::{Address, TxHash, U256};
-use alloy::providers::{Provider, ProviderBuilder, ReqwestProvider};
+use alloy::primitives::{Address, U256};
+use alloy::providers::Provider;
 use alloy::rpc::types::TransactionRequest;
-use alloy::signers::local::coins_bip39::{English, Mnemonic};
+use alloy::signers::local::coins_bip39::English;
 use alloy::signers::local::{MnemonicBuilder, PrivateKeySigner};
 use eyre::{eyre, Result};
 use log::{error, info};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
 use std::ops::Mul;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
 use tokio::task::JoinHandle;
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -22,6 +42,13 @@ pub enum InvoiceState {
     Complete,
     Rejected,
     Sent,
+    Confirming,
+    Sweeping,
+    SweepFailed,
+    SweepDeferred,
+    Expired,
+    PaymentPending,
+    SweepCancelled,
 }
 
 impl InvoiceState {
@@ -32,6 +59,13 @@ impl InvoiceState {
             Self::Complete => 2,
             Self::Rejected => 3,
             Self::Sent => 4,
+            Self::Confirming => 5,
+            Self::Sweeping => 6,
+            Self::SweepFailed => 7,
+            Self::SweepDeferred => 8,
+            Self::Expired => 9,
+            Self::PaymentPending => 10,
+            Self::SweepCancelled => 11,
         }
     }
 
@@ -42,6 +76,13 @@ impl InvoiceState {
             2 => Self::Complete,
             3 => Self::Rejected,
             4 => Self::Sent,
+            5 => Self::Confirming,
+            6 => Self::Sweeping,
+            7 => Self::SweepFailed,
+            8 => Self::SweepDeferred,
+            9 => Self::Expired,
+            10 => Self::PaymentPending,
+            11 => Self::SweepCancelled,
             _ => Self::Empty,
         }
     }
@@ -70,31 +111,78 @@ impl InvoiceAction {
     }
 }
 
+const MAX_SCANNED_BLOCKS: u64 = 100;
+
+/// Invoices checked in one pass of the invoice loop.
+struct InvoicePass {
+    invoices: Vec<Invoice>,
+    /// Invoices whose funds are waiting to be, or being, swept.
+    sweeps: Vec<Invoice>,
+    payments: HashMap<String, Vec<Payment>>,
+    indexed_block: Option<u64>,
+}
+
+struct PassOutcome {
+    /// Invoices whose sweep has to be started or advanced.
+    sweeps: Vec<Invoice>,
+    /// Invoices held back for quorum verification at the given block.
+    verifications: Vec<(Invoice, u64)>,
+}
+
+type InvoiceModel = crate::models::Invoice;
 type InvoiceManagerArc = Arc<Mutex<InvoiceManager>>;
-type ProviderArc = Arc<ReqwestProvider>;
+pub type ProviderArc = Arc<FailoverProvider>;
 
 pub struct InvoiceManager {
     provider: ProviderArc,
     invoice_service: InvoiceService,
     is_stopped: bool,
-    max_allowed_gas: u128,
-    max_priority_fee: u128,
+    sweeper: Sweeper,
+    hd_wallet: Arc<HdWallet>,
+    required_confirmations: u64,
+    poll_interval: Duration,
+    new_head: Arc<Notify>,
+    watch_mempool: bool,
+    /// Unmined transfers to open invoices by invoice address.
+    pending_payments: HashMap<String, PendingPayment>,
+    quorum: Option<Arc<Quorum>>,
+    price_source: Option<Arc<PriceSource>>,
+    /// Assets tokens are priced as, instead of their `symbol()`.
+    price_assets: HashMap<Address, String>,
 }
 
 impl InvoiceManager {
     pub async fn new(
-        rpc_url: String,
+        provider: ProviderArc,
         invoice_service: InvoiceService,
-        max_allowed_gas: u128,
-        max_priority_fee: u128,
+        sweep_settings: SweepSettings,
+        funding_station: Option<FundingStation>,
+        hd_wallet: Arc<HdWallet>,
+        required_confirmations: u64,
+        poll_interval: Duration,
     ) -> Arc<Mutex<Self>> {
-        let provider = Arc::new(ProviderBuilder::new().on_http(rpc_url.parse().unwrap()));
+        let sweeper = Sweeper {
+            provider: provider.clone(),
+            invoice_service: invoice_service.clone(),
+            settings: Arc::new(sweep_settings),
+            funding_station: funding_station.map(Arc::new),
+            required_confirmations,
+            busy: Arc::new(Mutex::new(())),
+        };
         Arc::new(Mutex::new(Self {
             provider,
             invoice_service,
             is_stopped: false,
-            max_allowed_gas,
-            max_priority_fee,
+            sweeper,
+            hd_wallet,
+            required_confirmations,
+            poll_interval,
+            new_head: Arc::new(Notify::new()),
+            watch_mempool: false,
+            pending_payments: HashMap::new(),
+            quorum: None,
+            price_source: None,
+            price_assets: HashMap::new(),
         }))
     }
 
@@ -103,139 +191,1356 @@ impl InvoiceManager {
         tokio::spawn(async move {
             'invoicemgr: loop {
                 let is_stopped;
-                let pending_invoices;
+                let pass;
+                let provider;
+                let quorum;
+                let sweeper;
+                let poll_interval;
+                let new_head;
 
                 {
-                    let mut self_lock = self_arc_clone.lock().await;
+                    let self_lock = self_arc_clone.lock().await;
                     is_stopped = self_lock.is_stopped;
-                    pending_invoices = self_lock.invoice_service.pending_invoices();
+                    poll_interval = self_lock.poll_interval;
+                    new_head = self_lock.new_head.clone();
                 }
 
                 if is_stopped {
                     break 'invoicemgr;
                 }
 
-                match pending_invoices {
-                    Ok(invoices) => {
-                        for mut invoice in invoices {
-                            if invoice.check_lifetime() {
-                                let mut self_lock = self_arc_clone.lock().await;
-                                match self_lock.update_invoice_state(&mut invoice).await {
-                                    Ok(_) => (),
-                                    Err(report) => error!("Failed update invoice {report}"),
+                if let Err(report) = Self::index_blocks(self_arc_clone.clone()).await {
+                    error!("Failed index blocks {report}");
+                }
+                if let Err(report) = Self::rescan_chunk(self_arc_clone.clone()).await {
+                    error!("Failed rescan blocks {report}");
+                }
+                if let Err(report) = Self::refresh_pending_payments(self_arc_clone.clone()).await {
+                    error!("Failed read mempool {report}");
+                }
+
+                {
+                    let self_lock = self_arc_clone.lock().await;
+                    pass = self_lock.database(Self::prepare_pass).await;
+                    provider = self_lock.provider.clone();
+                    quorum = self_lock.quorum.clone();
+                    sweeper = self_lock.sweeper.clone();
+                }
+
+                match pass {
+                    Ok(pass) => {
+                        // Balances are looked up without holding the lock, so a
+                        // slow node does not block the API.
+                        let balances = Self::fetch_pass_balances(provider.clone(), &pass).await;
+                        let indexed_at = Self::fetch_indexed_time(provider, &pass).await;
+                        let mut self_lock = self_arc_clone.lock().await;
+                        let outcome = self_lock.apply_pass(pass, balances, indexed_at).await;
+                        drop(self_lock);
+                        match outcome {
+                            Ok(outcome) => {
+                                for (invoice, block) in outcome.verifications {
+                                    let verified = match &quorum {
+                                        Some(quorum) => quorum.verify(&invoice, block).await,
+                                        None => Ok(true),
+                                    };
+                                    let mut self_lock = self_arc_clone.lock().await;
+                                    let completed =
+                                        match self_lock.complete_unchanged(invoice, verified).await
+                                        {
+                                            Ok(completed) => completed,
+                                            Err(report) => {
+                                                error!("Failed verify invoice {report}");
+                                                None
+                                            }
+                                        };
+                                    let sweep = completed.and_then(|invoice| {
+                                        Some((self_lock.sweeper_for(&invoice)?, invoice))
+                                    });
+                                    drop(self_lock);
+                                    if let Some((sweeper, invoice)) = sweep {
+                                        if let Err(report) =
+                                            sweeper.advance_unchanged(invoice).await
+                                        {
+                                            error!("Failed sweep invoice {report}");
+                                        }
+                                    }
+                                }
+                                // Sweeps are broadcast and checked without
+                                // holding the lock.
+                                for invoice in outcome.sweeps {
+                                    if let Err(report) = sweeper.advance_unchanged(invoice).await {
+                                        error!("Failed sweep invoice {report}");
+                                    }
                                 }
                             }
+                            Err(report) => error!("Failed update invoices {report}"),
                         }
                     }
                     Err(report) => error!("Could not retrieve data from service {report}"),
                 }
 
-                tokio::time::sleep(Duration::from_secs(60)).await;
+                Self::wait_for_next_pass(&new_head, poll_interval).await;
             }
         })
     }
 
-    async fn update_invoice_state(&mut self, invoice: &mut Invoice) -> Result<InvoiceState> {
-        let state = invoice.update_state(self.provider.clone()).await;
-
-        self.invoice_service
-            .update_invoice_state(invoice.address.clone(), state.clone())?;
-
-        if let InvoiceState::Complete = state {
-            if let InvoiceAction::SendToReceiver = invoice.complete_action {
-                match invoice
-                    .send_money_to_receiver(
-                        self.provider.clone(),
-                        self.max_priority_fee,
-                        self.max_allowed_gas,
-                    )
-                    .await
+    /// Sweeps invoices queued by a watch-only server. Runs in the `signer`
+    /// process, which holds the spending keys.
+    pub fn start_signer_loop(self_arc: InvoiceManagerArc) -> JoinHandle<()> {
+        let self_arc_clone = self_arc.clone();
+        tokio::spawn(async move {
+            'signer: loop {
+                let is_stopped;
+                let queued_sweeps;
+                let sweeper;
+                let poll_interval;
+                let new_head;
+
                 {
-                    Ok(_) => (),
-                    Err(e) => error!("{e}"),
-                };
-                self.invoice_service
-                    .update_invoice_state(invoice.address.clone(), InvoiceState::Sent)?;
+                    let self_lock = self_arc_clone.lock().await;
+                    is_stopped = self_lock.is_stopped;
+                    queued_sweeps = self_lock
+                        .database(|invoice_service| invoice_service.queued_sweeps())
+                        .await;
+                    sweeper = self_lock.sweeper.clone();
+                    poll_interval = self_lock.poll_interval;
+                    new_head = self_lock.new_head.clone();
+                }
+
+                if is_stopped {
+                    break 'signer;
+                }
+
+                match queued_sweeps {
+                    Ok(invoices) => {
+                        for invoice in invoices {
+                            if let Err(report) = sweeper.advance_unchanged(invoice).await {
+                                error!("Failed sweep invoice {report}");
+                            }
+                        }
+                    }
+                    Err(report) => error!("Could not retrieve data from service {report}"),
+                }
+
+                Self::wait_for_next_pass(&new_head, poll_interval).await;
+            }
+        })
+    }
+
+    /// Wakes the loops on every new block from `ws_url`. The loops fall back
+    /// to polling every `poll_interval` while the subscription is down.
+    pub async fn start_head_listener(
+        self_arc: InvoiceManagerArc,
+        ws_url: String,
+    ) -> JoinHandle<()> {
+        let new_head = self_arc.lock().await.new_head.clone();
+        heads::start_head_listener(ws_url, new_head)
+    }
+
+    /// Flags invoices with an unmined incoming transfer in the node
+    /// transaction pool as PaymentPending. Requires the `txpool` RPC namespace.
+    pub async fn watch_mempool(self_arc: InvoiceManagerArc) {
+        self_arc.lock().await.watch_mempool = true;
+    }
+
+    async fn refresh_pending_payments(self_arc: InvoiceManagerArc) -> Result<()> {
+        let (provider, invoice_service) = {
+            let self_lock = self_arc.lock().await;
+            if !self_lock.watch_mempool {
+                return Ok(());
+            }
+            (
+                self_lock.provider.clone(),
+                self_lock.invoice_service.clone(),
+            )
+        };
+        let watched = WatchedAddresses::new(
+            database(invoice_service, |invoice_service| {
+                invoice_service.watched_addresses()
+            })
+            .await?,
+        )?;
+        let pending_payments = mempool::pending_payments(provider, &watched).await?;
+        self_arc.lock().await.pending_payments = pending_payments;
+        Ok(())
+    }
+
+    /// Requires payments of high-value invoices to be confirmed by a quorum
+    /// of RPC endpoints before they complete.
+    pub async fn set_quorum(self_arc: InvoiceManagerArc, quorum: Quorum) {
+        self_arc.lock().await.quorum = Some(Arc::new(quorum));
+    }
+
+    /// Enables invoices with a fiat value, quoted from `price_source` when
+    /// they are created. Tokens in `price_assets` are priced as the given
+    /// asset, others by their `symbol()`.
+    pub async fn set_price_source(
+        self_arc: InvoiceManagerArc,
+        price_source: PriceSource,
+        price_assets: HashMap<Address, String>,
+    ) {
+        let mut self_lock = self_arc.lock().await;
+        self_lock.price_source = Some(Arc::new(price_source));
+        self_lock.price_assets = price_assets;
+    }
+
+    /// Runs database work on the blocking thread pool, see [`database`].
+    async fn database<T, F>(&self, work: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&InvoiceService) -> Result<T> + Send + 'static,
+    {
+        database(self.invoice_service.clone(), work).await
+    }
+
+    /// Whether the invoice has just completed and its payment still has to be
+    /// confirmed by the quorum.
+    fn needs_quorum(
+        quorum: Option<&Quorum>,
+        invoice: &Invoice,
+        previous_state: &InvoiceState,
+    ) -> bool {
+        matches!(invoice.state, InvoiceState::Complete)
+            && !matches!(previous_state, InvoiceState::Complete)
+            && quorum.is_some_and(|quorum| quorum.applies_to(invoice.value))
+    }
+
+    /// Completes an invoice held back for quorum verification, or leaves it
+    /// Confirming until the next pass when the endpoints do not agree. The
+    /// sweep of a completed invoice is left to the caller, see `sweeper_for`.
+    async fn complete_verified(
+        &mut self,
+        invoice: &mut Invoice,
+        verified: Result<bool>,
+    ) -> Result<InvoiceState> {
+        if !verified? {
+            info!(
+                target: "quorum",
+                "Payment of {} is not confirmed by quorum",
+                invoice.address
+            );
+            return Ok(invoice.state.clone());
+        }
+        invoice.state = InvoiceState::Complete;
+        let address = invoice.address.clone();
+        self.database(move |invoice_service| {
+            invoice_service.update_invoice_state(address, InvoiceState::Complete, None)
+        })
+        .await?;
+        Ok(InvoiceState::Complete)
+    }
+
+    /// Re-reads an invoice of the pass under the lock. Returns `None` when its
+    /// state changed since the pass, e.g. through `manual_check`.
+    async fn reload_unchanged(&self, invoice: &Invoice) -> Result<Option<Invoice>> {
+        let address = invoice.address.clone();
+        let current = self
+            .database(move |invoice_service| {
+                invoice_service.get_invoice_by_address(MerchantScope::All, address)
+            })
+            .await?;
+        if current.state.to_int() != invoice.state.to_int() {
+            info!(
+                "Invoice {} changed during the pass, skipped",
+                invoice.address
+            );
+            return Ok(None);
+        }
+        Ok(Some(current))
+    }
+
+    /// `complete_verified` for an invoice held back during the pass, unless
+    /// it was changed while the quorum was asked. Returns the invoice when it
+    /// was still unchanged.
+    async fn complete_unchanged(
+        &mut self,
+        invoice: Invoice,
+        verified: Result<bool>,
+    ) -> Result<Option<Invoice>> {
+        let Some(mut invoice) = self.reload_unchanged(&invoice).await? else {
+            return Ok(None);
+        };
+        self.complete_verified(&mut invoice, verified).await?;
+        Ok(Some(invoice))
+    }
+
+    /// The sweeper to advance the sweep of `invoice` with, outside the lock.
+    /// `None` when it has no sweep queued, or when the sweep is left to the
+    /// signer on a watch-only server.
+    fn sweeper_for(&self, invoice: &Invoice) -> Option<Sweeper> {
+        if !invoice.is_sweep_queued() {
+            return None;
+        }
+        if self.hd_wallet.is_watch_only() {
+            info!("Sweep of {} queued for signer", invoice.address);
+            return None;
+        }
+        Some(self.sweeper.clone())
+    }
+
+    async fn wait_for_next_pass(new_head: &Notify, poll_interval: Duration) {
+        let _ = tokio::time::timeout(poll_interval, new_head.notified()).await;
+    }
+
+    /// Open invoices with their recorded payments, and the invoices whose
+    /// sweep has to be advanced.
+    fn prepare_pass(invoice_service: &InvoiceService) -> Result<InvoicePass> {
+        let (sweeps, invoices): (Vec<_>, Vec<_>) = invoice_service
+            .pending_invoices()?
+            .into_iter()
+            .partition(Invoice::is_sweep_queued);
+        let payments = invoice_service.get_payments_by_invoice(
+            invoices
+                .iter()
+                .map(|invoice| invoice.address.clone())
+                .collect(),
+        )?;
+        Ok(InvoicePass {
+            invoices,
+            sweeps,
+            payments,
+            indexed_block: invoice_service.indexer_cursor()?,
+        })
+    }
+
+    /// Balances of the pass invoices at the indexed block and `confirmations`
+    /// blocks below it, in invoice order.
+    async fn fetch_pass_balances(
+        provider_arc: ProviderArc,
+        pass: &InvoicePass,
+    ) -> Result<Vec<(U256, U256)>> {
+        let mut queries = Vec::with_capacity(pass.invoices.len() * 2);
+        for invoice in &pass.invoices {
+            let address = invoice.address.parse::<Address>()?;
+            let token = invoice
+                .token
+                .as_ref()
+                .map(|token| token.parse::<Address>())
+                .transpose()?;
+            let block = pass.indexed_block.unwrap_or(invoice.scanned_block);
+            queries.push(BalanceQuery {
+                address,
+                token,
+                block,
+            });
+            queries.push(BalanceQuery {
+                address,
+                token,
+                block: confirmed_block(block, invoice.confirmations),
+            });
+        }
+        let balances = balances::fetch_balances(provider_arc, &queries).await?;
+        Ok(balances
+            .chunks(2)
+            .map(|balances| (balances[0], balances[1]))
+            .collect())
+    }
+
+    /// Timestamp of the indexed block, which the pass invoices expire against
+    /// so a lagging indexer does not expire invoices paid in blocks it has
+    /// not reached yet.
+    async fn fetch_indexed_time(provider_arc: ProviderArc, pass: &InvoicePass) -> Result<u64> {
+        let block = pass
+            .indexed_block
+            .ok_or_else(|| eyre!("No block indexed yet"))?;
+        indexer::block_timestamp(provider_arc, block).await
+    }
+
+    /// Updates the open invoices from their payments and balances and stores
+    /// them in one transaction. Falls back to the payments alone when the
+    /// balances could not be fetched, and expires no invoices when the indexed
+    /// block time is unknown. Invoices that need quorum verification are kept
+    /// Confirming and returned with the block to verify.
+    async fn apply_pass(
+        &mut self,
+        pass: InvoicePass,
+        balances: Result<Vec<(U256, U256)>>,
+        indexed_at: Result<u64>,
+    ) -> Result<PassOutcome> {
+        let balances = match balances {
+            Ok(balances) => Some(balances),
+            Err(report) => {
+                error!("Failed fetch balances, using recorded payments {report}");
+                None
+            }
+        };
+        let indexed_at = match indexed_at {
+            Ok(indexed_at) => indexed_at,
+            Err(report) => {
+                error!("Failed fetch indexed block time, not expiring invoices {report}");
+                0
             }
         };
+        let InvoicePass {
+            mut invoices,
+            sweeps,
+            payments,
+            indexed_block,
+        } = pass;
+
+        // Blocks at which invoices held back for the quorum are verified.
+        let mut verify_blocks = HashMap::new();
+        let mut previous_states = Vec::with_capacity(invoices.len());
+        for (index, invoice) in invoices.iter_mut().enumerate() {
+            let previous_state = invoice.state.clone();
+            invoice.pending_payment = self.pending_payments.get(&invoice.address).cloned();
+            let payments = payments
+                .get(&invoice.address)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let indexed_block = indexed_block.unwrap_or(invoice.scanned_block);
+            match balances.as_ref().map(|balances| balances[index]) {
+                Some((balance, confirmed_balance)) => invoice.update_state_from_balances(
+                    payments,
+                    indexed_block,
+                    indexed_at,
+                    balance,
+                    confirmed_balance,
+                ),
+                None => invoice.update_state_from_payments(payments, indexed_block, indexed_at),
+            };
+            if Self::needs_quorum(self.quorum.as_deref(), invoice, &previous_state) {
+                invoice.state = InvoiceState::Confirming;
+                verify_blocks.insert(
+                    invoice.address.clone(),
+                    confirmed_block(indexed_block, invoice.confirmations),
+                );
+            }
+            previous_states.push(previous_state);
+        }
+        // Invoices changed through the API while balances were fetched are
+        // picked up again on the next pass.
+        let (invoices, changed) = self
+            .database(move |invoice_service| {
+                let changed = invoice_service.update_invoice_states(&invoices, &previous_states)?;
+                Ok((invoices, changed))
+            })
+            .await?;
+
+        let mut outcome = PassOutcome {
+            sweeps: if self.hd_wallet.is_watch_only() {
+                Vec::new()
+            } else {
+                sweeps
+            },
+            verifications: Vec::new(),
+        };
+        for invoice in invoices {
+            if changed.contains(&invoice.address) {
+                info!(
+                    "Invoice {} changed during the pass, skipped",
+                    invoice.address
+                );
+                continue;
+            }
+            if let Some(block) = verify_blocks.remove(&invoice.address) {
+                outcome.verifications.push((invoice, block));
+            } else if invoice.is_sweep_queued() {
+                if self.hd_wallet.is_watch_only() {
+                    info!("Sweep of {} queued for signer", invoice.address);
+                } else {
+                    outcome.sweeps.push(invoice);
+                }
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Updates the invoice from the payments recorded by the indexer and its
+    /// balances at the indexed block, the same way a pass does. Invoices whose
+    /// funds are being swept are left to the sweeper.
+    async fn update_invoice_state(&mut self, invoice: &mut Invoice) -> Result<InvoiceState> {
+        if invoice.is_sweep_queued() {
+            return Ok(invoice.state.clone());
+        }
+
+        invoice.pending_payment = self.pending_payments.get(&invoice.address).cloned();
+        let address = invoice.address.clone();
+        let (payments, indexed_block) = self
+            .database(move |invoice_service| {
+                Ok((
+                    invoice_service.get_payments(address)?,
+                    invoice_service.indexer_cursor()?,
+                ))
+            })
+            .await?;
+        let indexed_block = indexed_block.unwrap_or(invoice.scanned_block);
+        let indexed_at = match indexer::block_timestamp(self.provider.clone(), indexed_block).await
+        {
+            Ok(indexed_at) => indexed_at,
+            Err(report) => {
+                error!("Failed fetch indexed block time, not expiring invoice {report}");
+                0
+            }
+        };
+        let balances = async {
+            let confirmed = confirmed_block(indexed_block, invoice.confirmations);
+            Ok::<_, eyre::Report>((
+                invoice
+                    .balance(self.provider.clone(), BlockId::number(indexed_block))
+                    .await?,
+                invoice
+                    .balance(self.provider.clone(), BlockId::number(confirmed))
+                    .await?,
+            ))
+        }
+        .await;
+
+        let previous_state = invoice.state.clone();
+        let state = match balances {
+            Ok((balance, confirmed_balance)) => invoice.update_state_from_balances(
+                &payments,
+                indexed_block,
+                indexed_at,
+                balance,
+                confirmed_balance,
+            ),
+            Err(report) => {
+                error!("Failed fetch balances, using recorded payments {report}");
+                invoice.update_state_from_payments(&payments, indexed_block, indexed_at)
+            }
+        };
+        if Self::needs_quorum(self.quorum.as_deref(), invoice, &previous_state) {
+            invoice.state = InvoiceState::Confirming;
+            let address = invoice.address.clone();
+            self.database(move |invoice_service| {
+                invoice_service.update_invoice_state(address, InvoiceState::Confirming, None)
+            })
+            .await?;
+            let verified = match self.quorum.clone() {
+                Some(quorum) => {
+                    quorum
+                        .verify(
+                            invoice,
+                            confirmed_block(indexed_block, invoice.confirmations),
+                        )
+                        .await
+                }
+                None => Ok(true),
+            };
+            return self.complete_verified(invoice, verified).await;
+        }
+
+        let address = invoice.address.clone();
+        let update_state = state.clone();
+        let pending_payment = invoice.pending_payment.clone();
+        self.database(move |invoice_service| {
+            invoice_service.update_invoice_state(address, update_state, pending_payment.as_ref())
+        })
+        .await?;
         Ok(state)
     }
 
-    pub async fn manual_check(&mut self, address: String) -> Result<InvoiceState> {
-        let mut invoice = self
-            .invoice_service
-            .get_invoice_by_address(address.clone())?;
-        Ok(self.update_invoice_state(&mut invoice).await?)
+    /// Indexes the blocks after the cursor, at most `MAX_SCANNED_BLOCKS` per
+    /// pass, recording transfers to open invoices. Blocks replaced by a reorg
+    /// are rolled back to the common ancestor first. Blocks are fetched
+    /// without holding the lock, which is only taken to store them.
+    async fn index_blocks(self_arc: InvoiceManagerArc) -> Result<()> {
+        let (provider, invoice_service) = {
+            let self_lock = self_arc.lock().await;
+            (
+                self_lock.provider.clone(),
+                self_lock.invoice_service.clone(),
+            )
+        };
+        let latest_block = provider.get_block_number().await?;
+        let (mut cursor, watched) = database(invoice_service.clone(), move |invoice_service| {
+            let cursor = match invoice_service.indexer_cursor()? {
+                Some(cursor) => cursor,
+                None => invoice_service
+                    .earliest_watched_block()?
+                    .unwrap_or(latest_block),
+            };
+            let watched = WatchedAddresses::new(invoice_service.watched_addresses()?)?;
+            Ok((cursor, watched))
+        })
+        .await?;
+        let to_block = latest_block.min(cursor + MAX_SCANNED_BLOCKS);
+
+        while cursor < to_block {
+            let block = indexer::scan_block(provider.clone(), cursor + 1, &watched).await?;
+            let parent_hash = database(invoice_service.clone(), move |invoice_service| {
+                invoice_service.indexed_block_hash(cursor)
+            })
+            .await?;
+            if parent_hash.is_some_and(|hash| hash != block.parent_hash.to_string()) {
+                let ancestor =
+                    Self::find_common_ancestor(provider.clone(), &invoice_service, cursor).await?;
+                error!(
+                    "Reorg at block {}, rolling back to block {ancestor}",
+                    block.number
+                );
+                let self_lock = self_arc.lock().await;
+                self_lock
+                    .database(move |invoice_service| invoice_service.rollback_index(ancestor))
+                    .await?;
+                cursor = ancestor;
+                continue;
+            }
+            let self_lock = self_arc.lock().await;
+            let ScannedBlock {
+                number,
+                hash,
+                payments,
+                ..
+            } = block;
+            self_lock
+                .database(move |invoice_service| {
+                    invoice_service.record_indexed_block(
+                        number,
+                        hash.to_string(),
+                        payments,
+                        indexer::REORG_DEPTH,
+                    )
+                })
+                .await?;
+            cursor = number;
+        }
+        Ok(())
+    }
+
+    pub async fn create_rescan(&mut self, from_block: u64, to_block: u64) -> Result<Rescan> {
+        if from_block > to_block {
+            return Err(eyre!(
+                "from_block {from_block} is after to_block {to_block}"
+            ));
+        }
+        self.database(move |invoice_service| invoice_service.create_rescan(from_block, to_block))
+            .await
+    }
+
+    /// Runs unfinished rescans to completion.
+    pub async fn run_rescans(self_arc: InvoiceManagerArc) -> Result<()> {
+        while Self::rescan_chunk(self_arc.clone()).await?.is_some() {}
+        Ok(())
+    }
+
+    /// Scans the next chunk of the oldest unfinished rescan against all known
+    /// invoice addresses, records missed payments and repairs the states of
+    /// the invoices paid in it. Blocks are scanned without holding the lock.
+    async fn rescan_chunk(self_arc: InvoiceManagerArc) -> Result<Option<Rescan>> {
+        let (rescan, watched, provider) = {
+            let self_lock = self_arc.lock().await;
+            let next_rescan = self_lock
+                .database(|invoice_service| {
+                    let Some(rescan) = invoice_service.next_rescan()? else {
+                        return Ok(None);
+                    };
+                    Ok(Some((rescan, invoice_service.invoice_addresses()?)))
+                })
+                .await?;
+            let Some((rescan, addresses)) = next_rescan else {
+                return Ok(None);
+            };
+            (
+                rescan,
+                WatchedAddresses::new(addresses)?,
+                self_lock.provider.clone(),
+            )
+        };
+        let last_block = rescan
+            .to_block
+            .min(rescan.next_block + MAX_SCANNED_BLOCKS - 1);
+
+        let mut payments = Vec::new();
+        for number in rescan.next_block..=last_block {
+            let block = indexer::scan_block(provider.clone(), number, &watched).await?;
+            payments.extend(block.payments);
+        }
+        let latest_block = provider.get_block_number().await?;
+
+        let self_lock = self_arc.lock().await;
+        let quorum = self_lock.quorum.clone();
+        self_lock
+            .database(move |invoice_service| {
+                Self::record_rescan_chunk(
+                    invoice_service,
+                    quorum.as_deref(),
+                    rescan,
+                    last_block,
+                    payments,
+                    latest_block,
+                )
+            })
+            .await
+            .map(Some)
+    }
+
+    /// Stores the payments found in a rescan chunk ending at `last_block` and
+    /// repairs the states of the invoices they were sent to.
+    fn record_rescan_chunk(
+        invoice_service: &InvoiceService,
+        quorum: Option<&Quorum>,
+        mut rescan: Rescan,
+        last_block: u64,
+        payments: Vec<Payment>,
+        latest_block: u64,
+    ) -> Result<Rescan> {
+        let addresses: BTreeSet<String> = payments
+            .iter()
+            .map(|payment| payment.invoice_address.clone())
+            .collect();
+
+        let missed_payments = invoice_service.insert_payments(payments)?;
+        for payment in &missed_payments {
+            info!(
+                target: "rescan",
+                "Missed payment {} of {} to {} in block {}",
+                payment.tx_hash, payment.amount, payment.invoice_address, payment.block_number
+            );
+        }
+
+        let mut repaired_invoices = 0;
+        for address in addresses {
+            if Self::repair_invoice_state(invoice_service, quorum, address, latest_block)? {
+                repaired_invoices += 1;
+            }
+        }
+
+        rescan.advance(last_block, missed_payments.len() as u32, repaired_invoices);
+        invoice_service.save_rescan(&rescan)?;
+        if let RescanStatus::Done = rescan.status {
+            info!(
+                target: "rescan",
+                "Rescan {} of blocks {}..={} done: {} missed payments, {} repaired invoices",
+                rescan.id,
+                rescan.from_block,
+                rescan.to_block,
+                rescan.missed_payments,
+                rescan.repaired_invoices
+            );
+        }
+        Ok(rescan)
+    }
+
+    /// Recomputes the state of an invoice that is not being swept from its
+    /// recorded payments. Returns whether the state changed.
+    fn repair_invoice_state(
+        invoice_service: &InvoiceService,
+        quorum: Option<&Quorum>,
+        address: String,
+        latest_block: u64,
+    ) -> Result<bool> {
+        let mut invoice =
+            invoice_service.get_invoice_by_address(MerchantScope::All, address.clone())?;
+        let old_state = invoice.state.to_int();
+        if !matches!(
+            invoice.state,
+            InvoiceState::Empty
+                | InvoiceState::Incomplete
+                | InvoiceState::Confirming
+                | InvoiceState::PaymentPending
+                | InvoiceState::Rejected
+                | InvoiceState::Expired
+        ) {
+            return Ok(false);
+        }
+
+        let payments = invoice_service.get_payments(address.clone())?;
+        let previous_state = invoice.state.clone();
+        let mut state = invoice.update_state_from_payments(&payments, latest_block, now());
+        // Left for the invoice loop to verify and complete.
+        if Self::needs_quorum(quorum, &invoice, &previous_state) {
+            state = InvoiceState::Confirming;
+        }
+        if state.to_int() == old_state {
+            return Ok(false);
+        }
+        info!(
+            target: "rescan",
+            "Invoice {address} state repaired from {old_state} to {}",
+            state.to_int()
+        );
+        invoice_service.update_invoice_state(address, state, invoice.pending_payment.as_ref())?;
+        Ok(true)
+    }
+
+    /// Latest indexed block at or below `from` that is still canonical.
+    async fn find_common_ancestor(
+        provider_arc: ProviderArc,
+        invoice_service: &InvoiceService,
+        from: u64,
+    ) -> Result<u64> {
+        indexer::common_ancestor(
+            from,
+            |number| {
+                database(invoice_service.clone(), move |invoice_service| {
+                    invoice_service.indexed_block_hash(number)
+                })
+            },
+            |number| indexer::block_hash(provider_arc.clone(), number),
+        )
+        .await
     }
 
+    /// Refreshes the invoice state and advances its sweep. The sweep is
+    /// broadcast or checked after the lock is released.
+    pub async fn manual_check(
+        self_arc: InvoiceManagerArc,
+        scope: MerchantScope,
+        address: String,
+    ) -> Result<InvoiceState> {
+        let (invoice, sweeper) = {
+            let mut self_lock = self_arc.lock().await;
+            let mut invoice = self_lock
+                .database(move |invoice_service| {
+                    invoice_service.get_invoice_by_address(scope, address)
+                })
+                .await?;
+            self_lock.update_invoice_state(&mut invoice).await?;
+            let sweeper = self_lock.sweeper_for(&invoice);
+            (invoice, sweeper)
+        };
+        match sweeper {
+            Some(sweeper) => sweeper.advance_unchanged(invoice).await,
+            None => Ok(invoice.state),
+        }
+    }
+
+    /// Creates an invoice and returns its address. Token decimals and fiat
+    /// quotes are fetched without holding the lock, which is only taken to
+    /// derive and store the invoice wallet.
     pub async fn create_invoice(
-        &mut self,
-        receiver: String,
-        value: f64,
-        lifetime: u64,
-        action: Option<u32>,
+        self_arc: InvoiceManagerArc,
+        request: InvoiceRequest,
     ) -> Result<String> {
-        let action = match action {
-            Some(action) => InvoiceAction::from_int(action),
-            _ => InvoiceAction::Nothing,
+        let InvoiceRequest {
+            receiver,
+            value,
+            lifetime,
+            action,
+            token,
+            confirmations,
+            merchant_id,
+            webhook_url,
+        } = request;
+
+        receiver
+            .parse::<Address>()
+            .map_err(|_| InvalidRequest(format!("Invalid receiver {receiver}")))?;
+        let token = token
+            .map(|token| {
+                token
+                    .parse::<Address>()
+                    .map_err(|_| InvalidRequest(format!("Invalid token {token}")))
+            })
+            .transpose()?;
+        let (provider, price_source, price_asset, required_confirmations) = {
+            let self_lock = self_arc.lock().await;
+            (
+                self_lock.provider.clone(),
+                self_lock.price_source.clone(),
+                token.and_then(|token| self_lock.price_assets.get(&token).cloned()),
+                self_lock.required_confirmations,
+            )
+        };
+        let decimals = match token {
+            Some(token) => erc20::decimals(provider.clone(), token).await?,
+            None => 18,
+        };
+        let (value, fiat) = match value {
+            InvoiceValue::Crypto(value) => (parse_amount(&value, decimals)?, None),
+            InvoiceValue::Fiat(fiat_value) => {
+                let price_source = price_source.ok_or_else(|| {
+                    InvalidRequest("Fiat invoices need PRICE_SOURCE to be configured".to_string())
+                })?;
+                let asset = match (token, price_asset) {
+                    (_, Some(asset)) => asset,
+                    (Some(token), None) => erc20::symbol(provider.clone(), token)
+                        .await
+                        .map_err(|report| {
+                            eyre!("Could not read symbol of {token}, set it in PRICE_ASSETS: {report}")
+                        })?,
+                    (None, None) => "ETH".to_string(),
+                };
+                let fiat = price_source.quote(&fiat_value, &asset).await?;
+                (fiat.base_units(decimals)?, Some(fiat))
+            }
         };
+        let scanned_block = provider.get_block_number().await?;
 
-        let invoice = Invoice::new(receiver, value, lifetime, action);
+        let self_lock = self_arc.lock().await;
+        let derivation_index = self_lock
+            .database(|invoice_service| invoice_service.next_derivation_index())
+            .await?;
+        let mut invoice = Invoice::new(
+            &self_lock.hd_wallet,
+            derivation_index,
+            receiver,
+            value,
+            lifetime,
+            action,
+            confirmations.unwrap_or(required_confirmations),
+        )?;
+        if let Some(token) = token {
+            invoice = invoice.with_token(token.to_string(), decimals);
+        }
+        if let Some(merchant_id) = merchant_id {
+            invoice = invoice.with_merchant(merchant_id, webhook_url);
+        }
+        if let Some(fiat) = fiat {
+            invoice = invoice.with_fiat(fiat);
+        }
+        invoice.scanned_block = scanned_block;
         let address = invoice.address.clone();
-        self.invoice_service.create_invoice(invoice)?;
+        self_lock
+            .database(move |invoice_service| invoice_service.create_invoice(invoice))
+            .await?;
 
         Ok(address)
     }
 
-    pub fn get_invoice_by_int_state(&mut self, state: u32) -> Result<Vec<Invoice>> {
-        Ok(self
-            .invoice_service
-            .get_invoices_by_state(InvoiceState::from_int(state))?)
+    pub async fn stop_loop(self_arc: InvoiceManagerArc) {
+        self_arc.lock().await.is_stopped = true;
     }
+}
 
-    pub fn get_invoice_by_int_action(&mut self, action: u32) -> Result<Vec<Invoice>> {
-        Ok(self
-            .invoice_service
-            .get_invoices_by_action(InvoiceAction::from_int(action))?)
+/// Runs database work on the blocking thread pool. All database access of the
+/// manager goes through here, as API handlers reach it on a runtime that does
+/// not support `block_in_place`.
+async fn database<T, F>(invoice_service: InvoiceService, work: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&InvoiceService) -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || work(&invoice_service)).await?
+}
+
+/// Broadcasts and follows sweeps. Cloned out of the manager so sweep
+/// transactions are built, sent and checked without holding its lock.
+#[derive(Clone)]
+struct Sweeper {
+    provider: ProviderArc,
+    invoice_service: InvoiceService,
+    settings: Arc<SweepSettings>,
+    funding_station: Option<Arc<FundingStation>>,
+    required_confirmations: u64,
+    /// Held while a sweep is advanced, so that the loop, the signer and
+    /// manual checks never advance sweeps at the same time.
+    busy: Arc<Mutex<()>>,
+}
+
+impl Sweeper {
+    async fn database<T, F>(&self, work: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&InvoiceService) -> Result<T> + Send + 'static,
+    {
+        database(self.invoice_service.clone(), work).await
     }
 
-    pub fn get_invoice_by_address(&mut self, address: String) -> Result<Invoice> {
-        Ok(self.invoice_service.get_invoice_by_address(address)?)
+    /// `advance_sweep` for an invoice read earlier, unless its state changed
+    /// in the meantime. Returns the current state either way.
+    async fn advance_unchanged(&self, invoice: Invoice) -> Result<InvoiceState> {
+        let _busy = self.busy.lock().await;
+        let address = invoice.address.clone();
+        let mut current = self
+            .database(move |invoice_service| {
+                invoice_service.get_invoice_by_address(MerchantScope::All, address)
+            })
+            .await?;
+        if current.state.to_int() != invoice.state.to_int() {
+            info!(
+                "Invoice {} changed before its sweep, skipped",
+                invoice.address
+            );
+            return Ok(current.state);
+        }
+        self.advance_sweep(&mut current).await
     }
 
-    pub async fn stop_loop(self_arc: InvoiceManagerArc) {
-        self_arc.lock().await.is_stopped = true;
+    /// Moves a queued sweep forward: broadcasts it, waits for its receipt or
+    /// retries it once its backoff has passed. Deferred sweeps are retried on
+    /// every pass until fees drop.
+    async fn advance_sweep(&self, invoice: &mut Invoice) -> Result<InvoiceState> {
+        match invoice.state {
+            InvoiceState::Sweeping => self.check_sweep(invoice).await,
+            InvoiceState::SweepFailed => {
+                let address = invoice.address.clone();
+                let can_retry = self
+                    .database(move |invoice_service| invoice_service.get_sweep(address))
+                    .await?
+                    .is_none_or(|sweep| sweep.can_retry());
+                if can_retry {
+                    self.sweep_invoice(invoice).await
+                } else {
+                    Ok(invoice.state.clone())
+                }
+            }
+            _ => self.sweep_invoice(invoice).await,
+        }
+    }
+
+    async fn sweep_invoice(&self, invoice: &mut Invoice) -> Result<InvoiceState> {
+        let address = invoice.address.clone();
+        let mut sweep = self
+            .database(move |invoice_service| invoice_service.get_sweep(address))
+            .await?
+            .unwrap_or_else(|| Sweep::new(invoice.address.clone()));
+        sweep.cancel_requested = false;
+
+        let urgency = self.settings.urgency;
+        let transaction = match self.current_fees(urgency).await {
+            Ok(fees) => match self.top_up_for_sweep(invoice, &mut sweep, fees).await {
+                Ok(false) => {
+                    self.save_sweep(sweep, invoice.state.clone(), None).await?;
+                    return Ok(invoice.state.clone());
+                }
+                Ok(true) => {
+                    invoice
+                        .send_money_to_receiver(
+                            self.provider.clone(),
+                            fees,
+                            None,
+                            self.settings.max_allowed_gas,
+                        )
+                        .await
+                }
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        let state = match &transaction {
+            Ok(transaction) => {
+                sweep.attempts += 1;
+                sweep.submitted(transaction);
+                InvoiceState::Sweeping
+            }
+            Err(e) if e.is::<SweepTooExpensive>() => {
+                info!("Sweep of {} deferred: {e}", invoice.address);
+                if sweep.deferred(e.to_string(), self.settings.defer_deadline) {
+                    error!(
+                        target: "alert",
+                        "Sweep of {} is still deferred after its deadline: {e}",
+                        invoice.address
+                    );
+                }
+                InvoiceState::SweepDeferred
+            }
+            Err(e) => {
+                sweep.attempts += 1;
+                error!(
+                    "Sweep attempt {}/{MAX_SWEEP_ATTEMPTS} of {} failed: {e}",
+                    sweep.attempts, invoice.address
+                );
+                sweep_failed(&mut sweep, e.to_string());
+                InvoiceState::SweepFailed
+            }
+        };
+        self.save_sweep(sweep, state.clone(), transaction.ok())
+            .await?;
+        invoice.state = state.clone();
+        Ok(state)
+    }
+
+    /// Marks the invoice Sent once any transaction broadcast with the sweep
+    /// nonce has enough confirmations. A sweep that reverted or was dropped
+    /// fails, a stuck one is replaced with higher fees, and a confirmed cancel
+    /// leaves the funds in the invoice wallet.
+    async fn check_sweep(&self, invoice: &mut Invoice) -> Result<InvoiceState> {
+        let address = invoice.address.clone();
+        let mut sweep = self
+            .database(move |invoice_service| invoice_service.get_sweep(address))
+            .await?
+            .ok_or_else(|| eyre!("No sweep recorded for {}", invoice.address))?;
+        let nonce = sweep
+            .nonce
+            .ok_or_else(|| eyre!("Sweep of {} has no transaction", invoice.address))?;
+        let address = invoice.address.clone();
+        let transactions = self
+            .database(move |invoice_service| invoice_service.get_sweep_transactions(address, nonce))
+            .await?;
+        let latest_transaction = transactions
+            .first()
+            .ok_or_else(|| eyre!("Sweep of {} has no transaction", invoice.address))?;
+
+        let mut mined = None;
+        for transaction in &transactions {
+            if let Some(receipt) = self
+                .provider
+                .get_transaction_receipt(transaction.tx_hash)
+                .await?
+            {
+                mined = Some((transaction, receipt));
+                break;
+            }
+        }
+
+        let state = match mined {
+            Some((transaction, receipt)) if !receipt.status() => {
+                error!(
+                    "Sweep {} of {} reverted",
+                    transaction.tx_hash, invoice.address
+                );
+                sweep_failed(
+                    &mut sweep,
+                    format!("Transaction {} reverted", transaction.tx_hash),
+                );
+                InvoiceState::SweepFailed
+            }
+            Some((transaction, receipt)) => {
+                let latest_block = self.provider.get_block_number().await?;
+                let confirmations = receipt
+                    .block_number
+                    .map_or(0, |block| confirmations(block, latest_block));
+                if confirmations < self.required_confirmations {
+                    return Ok(InvoiceState::Sweeping);
+                }
+                if transaction.kind == SweepTransactionKind::Cancel {
+                    info!(
+                        "Sweep of {} cancelled by {}",
+                        invoice.address, transaction.tx_hash
+                    );
+                    sweep.cancelled();
+                    InvoiceState::SweepCancelled
+                } else {
+                    info!(
+                        "Sweep {} of {} confirmed",
+                        transaction.tx_hash, invoice.address
+                    );
+                    sweep.confirmed();
+                    InvoiceState::Sent
+                }
+            }
+            None => {
+                let wallet_nonce = self
+                    .provider
+                    .get_transaction_count(invoice.address.parse()?)
+                    .await?;
+                let is_pending = self
+                    .provider
+                    .get_transaction_by_hash(latest_transaction.tx_hash)
+                    .await?
+                    .is_some();
+                if wallet_nonce > nonce {
+                    error!(
+                        "Sweep nonce {nonce} of {} used by unknown transaction",
+                        invoice.address
+                    );
+                    sweep_failed(
+                        &mut sweep,
+                        format!("Nonce {nonce} used by unknown transaction"),
+                    );
+                    InvoiceState::SweepFailed
+                } else if !is_pending {
+                    error!(
+                        "Sweep {} of {} dropped",
+                        latest_transaction.tx_hash, invoice.address
+                    );
+                    sweep_failed(
+                        &mut sweep,
+                        format!("Transaction {} dropped", latest_transaction.tx_hash),
+                    );
+                    InvoiceState::SweepFailed
+                } else {
+                    let is_cancel_pending = latest_transaction.kind == SweepTransactionKind::Cancel;
+                    if sweep.cancel_requested && !is_cancel_pending {
+                        self.replace_sweep(invoice, &mut sweep, true).await?;
+                    } else if sweep.is_stuck(self.settings.stuck_timeout) {
+                        self.replace_sweep(invoice, &mut sweep, is_cancel_pending)
+                            .await?;
+                    }
+                    return Ok(InvoiceState::Sweeping);
+                }
+            }
+        };
+        self.save_sweep(sweep, state.clone(), None).await?;
+        invoice.state = state.clone();
+        Ok(state)
+    }
+
+    /// Re-broadcasts a pending sweep with the same nonce and bumped fees,
+    /// either as a sweep or as a cancelling self-transfer.
+    async fn replace_sweep(
+        &self,
+        invoice: &Invoice,
+        sweep: &mut Sweep,
+        cancel: bool,
+    ) -> Result<()> {
+        let (Some(nonce), Some(fees)) = (sweep.nonce, sweep.fees()) else {
+            return Err(eyre!("Sweep of {} has no transaction", invoice.address));
+        };
+        let fees = fees.bumped(self.current_fees(Urgency::High).await?);
+        let max_allowed_gas = self.settings.max_allowed_gas;
+
+        let transaction = if cancel {
+            invoice
+                .cancel_sweep(self.provider.clone(), fees, nonce, max_allowed_gas)
+                .await
+        } else {
+            invoice
+                .send_money_to_receiver(self.provider.clone(), fees, Some(nonce), max_allowed_gas)
+                .await
+                .map(|transaction| SweepTransaction {
+                    kind: SweepTransactionKind::Replacement,
+                    ..transaction
+                })
+        };
+        match transaction {
+            Ok(transaction) => {
+                info!(
+                    "Replaced sweep of {} with {} at max fee {}",
+                    invoice.address, transaction.tx_hash, fees.max_fee_per_gas
+                );
+                sweep.submitted(&transaction);
+                self.save_sweep(sweep.clone(), InvoiceState::Sweeping, Some(transaction))
+                    .await?;
+            }
+            Err(e) => error!("Could not replace sweep of {}: {e}", invoice.address),
+        }
+        Ok(())
+    }
+
+    async fn save_sweep(
+        &self,
+        sweep: Sweep,
+        invoice_state: InvoiceState,
+        transaction: Option<SweepTransaction>,
+    ) -> Result<()> {
+        self.database(move |invoice_service| {
+            invoice_service.save_sweep(&sweep, invoice_state, transaction.as_ref())
+        })
+        .await
+    }
+
+    async fn current_fees(&self, urgency: Urgency) -> Result<SweepFees> {
+        fees::estimate_fees(
+            self.provider.clone(),
+            urgency,
+            self.settings.max_priority_fee,
+        )
+        .await
+    }
+
+    /// Funds a token invoice wallet with enough ETH to pay for its sweep.
+    /// The top-up is only broadcast, so this returns false until it is mined
+    /// and the sweep goes on in a later pass.
+    async fn top_up_for_sweep(
+        &self,
+        invoice: &Invoice,
+        sweep: &mut Sweep,
+        fees: SweepFees,
+    ) -> Result<bool> {
+        let funding_station = match (&invoice.token, &self.funding_station) {
+            (Some(_), Some(funding_station)) => funding_station,
+            _ => return Ok(true),
+        };
+
+        if let Some(tx_hash) = sweep.top_up_tx_hash.clone() {
+            let hash = tx_hash.parse()?;
+            let transaction = self.provider.get_transaction_by_hash(hash).await?;
+            let receipt = self.provider.get_transaction_receipt(hash).await?;
+            match (transaction, receipt) {
+                (Some(transaction), Some(receipt)) => {
+                    sweep.top_up_tx_hash = None;
+                    let address = invoice.address.clone();
+                    let cost = transaction.value
+                        + U256::from(receipt.gas_used * receipt.effective_gas_price);
+                    self.database(move |invoice_service| {
+                        invoice_service.add_top_up_cost(address, cost)
+                    })
+                    .await?;
+                    if !receipt.status() {
+                        return Err(eyre!("Top-up {tx_hash} of {} reverted", invoice.address));
+                    }
+                }
+                (Some(_), None) => {
+                    info!("Top-up {tx_hash} of {} is not mined yet", invoice.address);
+                    return Ok(false);
+                }
+                (None, _) => {
+                    error!("Top-up {tx_hash} of {} dropped", invoice.address);
+                    sweep.top_up_tx_hash = None;
+                }
+            }
+        }
+
+        let max_gas_cost = invoice
+            .token_sweep_gas_cost(self.provider.clone(), fees)
+            .await?;
+        if max_gas_cost > U256::from(self.settings.max_allowed_gas) {
+            return Err(SweepTooExpensive {
+                cost: max_gas_cost,
+                max_allowed_gas: self.settings.max_allowed_gas,
+            }
+            .into());
+        }
+
+        let address = invoice.address.parse::<Address>()?;
+        let balance = self.provider.get_balance(address).await?;
+        if balance >= max_gas_cost {
+            return Ok(true);
+        }
+
+        let tx_hash = funding_station
+            .top_up(self.provider.clone(), address, max_gas_cost - balance, fees)
+            .await?;
+        sweep.top_up_tx_hash = Some(tx_hash.to_string());
+        Ok(false)
     }
 }
 
-#[derive(Serialize)]
+/// Amount due, either with a crypto unit or in fiat quoted from the price
+/// source. Both are `"<decimal number> <unit>"`.
+pub enum InvoiceValue {
+    Crypto(String),
+    Fiat(String),
+}
+
+/// Invoice to create, with merchant defaults already applied.
+pub struct InvoiceRequest {
+    pub receiver: String,
+    pub value: InvoiceValue,
+    /// Seconds.
+    pub lifetime: u64,
+    pub action: InvoiceAction,
+    pub token: Option<String>,
+    pub confirmations: Option<u64>,
+    pub merchant_id: Option<i32>,
+    /// Recorded on invoices of a merchant.
+    pub webhook_url: Option<String>,
+}
+
 pub struct Invoice {
     pub address: String,
-    #[serde(skip)]
-    wallet: PrivateKeySigner,
+    wallet: Option<PrivateKeySigner>,
     pub receiver: String,
-    pub mnemonic: String,
-    pub value: f64,
+    pub mnemonic: Option<String>,
+    pub derivation_index: Option<u32>,
+    pub value: U256,
     pub state: InvoiceState,
     pub lifetime: u64,
     pub complete_action: InvoiceAction,
+    pub confirmations: u64,
+    pub token: Option<String>,
+    pub decimals: u8,
+    pub top_up_cost: U256,
+    /// Last block searched for incoming payments.
+    pub scanned_block: u64,
+    /// Unmined transfer seen while the invoice is PaymentPending.
+    pub pending_payment: Option<PendingPayment>,
+    pub merchant_id: Option<i32>,
+    pub webhook_url: Option<String>,
+    /// Fiat amount and locked rate of invoices created in fiat.
+    pub fiat: Option<FiatQuote>,
 }
 
 impl Invoice {
-    pub fn new(receiver: String, value: f64, lifetime: u64, action: InvoiceAction) -> Self {
-        let mut rand = rand::thread_rng();
-        let mnemonic = Mnemonic::<English>::new_with_count(&mut rand, 24)
-            .unwrap()
-            .to_phrase();
-        let wallet = MnemonicBuilder::<English>::default()
-            .phrase(mnemonic.clone())
-            .build()
-            .unwrap();
-        Self {
-            address: wallet.address().to_string(),
-            wallet,
+    pub fn new(
+        hd_wallet: &HdWallet,
+        derivation_index: u32,
+        receiver: String,
+        value: U256,
+        lifetime: u64,
+        action: InvoiceAction,
+        confirmations: u64,
+    ) -> Result<Self> {
+        Ok(Self {
+            address: hd_wallet.address(derivation_index)?.to_string(),
+            wallet: hd_wallet.signer(derivation_index)?,
             receiver,
-            mnemonic,
+            mnemonic: None,
+            derivation_index: Some(derivation_index),
             value,
             state: InvoiceState::Empty,
             lifetime: (SystemTime::now()
@@ -244,89 +1549,341 @@ impl Invoice {
                 + Duration::from_secs(lifetime))
             .as_secs(),
             complete_action: action,
-        }
+            confirmations,
+            token: None,
+            decimals: 18,
+            top_up_cost: U256::from(0),
+            scanned_block: 0,
+            pending_payment: None,
+            merchant_id: None,
+            webhook_url: None,
+            fiat: None,
+        })
     }
 
-    pub fn load(
-        mnemonic: String,
-        receiver: String,
-        value: f64,
-        state: u32,
-        lifetime: u64,
-        action: u32,
-    ) -> Self {
-        let wallet = MnemonicBuilder::<English>::default()
-            .phrase(mnemonic.clone())
-            .build()
-            .unwrap();
-        Self {
-            address: wallet.address().to_string(),
-            wallet,
-            mnemonic,
-            receiver,
-            value,
-            state: InvoiceState::from_int(state),
-            lifetime,
-            complete_action: InvoiceAction::from_int(action),
-        }
+    pub fn with_token(mut self, token: String, decimals: u8) -> Self {
+        self.token = Some(token);
+        self.decimals = decimals;
+        self
     }
 
-    pub async fn update_state(&mut self, provider_ark: ProviderArc) -> InvoiceState {
-        let self_balance = wei_to_eth(
-            provider_ark
-                .get_balance(self.wallet.address())
-                .await
-                .unwrap(),
-        );
-        let state = match self_balance {
-            balance if balance == 0.0 => {
-                if self.check_lifetime() {
-                    InvoiceState::Rejected
-                } else {
-                    InvoiceState::Empty
-                }
+    pub fn with_merchant(mut self, merchant_id: i32, webhook_url: Option<String>) -> Self {
+        self.merchant_id = Some(merchant_id);
+        self.webhook_url = webhook_url;
+        self
+    }
+
+    pub fn with_fiat(mut self, fiat: FiatQuote) -> Self {
+        self.fiat = Some(fiat);
+        self
+    }
+
+    /// Rebuilds the invoice wallet from its derivation index, or from the
+    /// mnemonic stored for invoices created before HD derivation. Watch-only
+    /// wallets load no signer at all.
+    pub fn load(model: InvoiceModel, hd_wallet: &HdWallet) -> Result<Self> {
+        let wallet = match (model.derivation_index, &model.mnemonic) {
+            _ if hd_wallet.is_watch_only() => None,
+            (Some(index), _) => hd_wallet.signer(index as u32)?,
+            (None, Some(mnemonic)) => Some(
+                MnemonicBuilder::<English>::default()
+                    .phrase(mnemonic.clone())
+                    .build()?,
+            ),
+            (None, None) => {
+                return Err(eyre!(
+                    "Invoice {} has neither derivation index nor mnemonic",
+                    model.address
+                ))
             }
-            balance if balance < self.value => InvoiceState::Incomplete,
-            balance if balance >= self.value => InvoiceState::Complete,
-            _ => {
-                if self.check_lifetime() {
-                    InvoiceState::Rejected
-                } else {
-                    InvoiceState::Empty
-                }
+        };
+        Ok(Self {
+            address: model.address,
+            wallet,
+            mnemonic: model.mnemonic,
+            derivation_index: model.derivation_index.map(|index| index as u32),
+            receiver: model.receiver,
+            value: numeric_to_u256(model.value)?,
+            state: InvoiceState::from_int(model.state as u32),
+            lifetime: model.lifetime as u64,
+            complete_action: InvoiceAction::from_int(model.complete_action as u32),
+            confirmations: model.confirmations as u64,
+            token: model.token,
+            decimals: model.decimals as u8,
+            top_up_cost: numeric_to_u256(model.top_up_cost)?,
+            scanned_block: model.scanned_block as u64,
+            pending_payment: model
+                .pending_tx_hash
+                .zip(model.pending_amount)
+                .map(|(tx_hash, amount)| -> Result<PendingPayment> {
+                    Ok(PendingPayment {
+                        tx_hash,
+                        amount: numeric_to_u256(amount)?,
+                    })
+                })
+                .transpose()?,
+            merchant_id: model.merchant_id,
+            webhook_url: model.webhook_url,
+            fiat: match (
+                model.fiat_amount,
+                model.fiat_currency,
+                model.exchange_rate,
+                model.rate_timestamp,
+            ) {
+                (Some(amount), Some(currency), Some(rate), Some(timestamp)) => Some(FiatQuote {
+                    amount,
+                    currency,
+                    rate,
+                    timestamp: timestamp as u64,
+                }),
+                _ => None,
+            },
+        })
+    }
+
+    /// Sets the state from the payments recorded up to `indexed_block`, mined
+    /// at `indexed_at`.
+    pub fn update_state_from_payments(
+        &mut self,
+        payments: &[Payment],
+        indexed_block: u64,
+        indexed_at: u64,
+    ) -> InvoiceState {
+        let (paid, confirmed_paid) = self.paid(payments, indexed_block);
+        self.apply_balances(paid, confirmed_paid, indexed_at)
+    }
+
+    /// Same as `update_state_from_payments`, but also counts the wallet
+    /// balances at `indexed_block`, which include ETH sent by internal calls
+    /// that the indexer does not see.
+    pub fn update_state_from_balances(
+        &mut self,
+        payments: &[Payment],
+        indexed_block: u64,
+        indexed_at: u64,
+        balance: U256,
+        confirmed_balance: U256,
+    ) -> InvoiceState {
+        let (paid, confirmed_paid) = self.paid(payments, indexed_block);
+        self.apply_balances(
+            paid.max(balance),
+            confirmed_paid.max(confirmed_balance),
+            indexed_at,
+        )
+    }
+
+    /// Total paid and total paid with enough confirmations at `indexed_block`.
+    fn paid(&self, payments: &[Payment], indexed_block: u64) -> (U256, U256) {
+        let paid = payments
+            .iter()
+            .fold(U256::ZERO, |paid, payment| paid + payment.amount);
+        let confirmed_paid = payments
+            .iter()
+            .filter(|payment| {
+                confirmations(payment.block_number, indexed_block) >= self.confirmations
+            })
+            .fold(U256::ZERO, |paid, payment| paid + payment.amount);
+        (paid, confirmed_paid)
+    }
+
+    /// Sets the state from the balances observed at unix time `observed_at`.
+    fn apply_balances(
+        &mut self,
+        balance: U256,
+        confirmed_balance: U256,
+        observed_at: u64,
+    ) -> InvoiceState {
+        let state = match balance {
+            balance if balance.is_zero() => InvoiceState::Empty,
+            _ if confirmed_balance >= self.value => InvoiceState::Complete,
+            balance if balance >= self.value => InvoiceState::Confirming,
+            _ => InvoiceState::Incomplete,
+        };
+        // A transfer still in the mempool never completes the invoice.
+        let state = match state {
+            InvoiceState::Empty | InvoiceState::Incomplete if self.pending_payment.is_some() => {
+                InvoiceState::PaymentPending
             }
+            state => state,
         };
+        if !matches!(state, InvoiceState::PaymentPending) {
+            self.pending_payment = None;
+        }
+        let state = self.expire(state, observed_at);
         self.state = state.clone();
         state
     }
 
-    fn check_lifetime(&self) -> bool {
-        SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            >= self.lifetime
+    /// Invoices that are unpaid when their lifetime ends are Rejected and
+    /// underpaid ones Expired. A full payment still waiting for confirmations
+    /// is allowed to complete. The lifetime is compared to `observed_at`, the
+    /// time the balances were observed at, not to the current time.
+    fn expire(&self, state: InvoiceState, observed_at: u64) -> InvoiceState {
+        if observed_at < self.lifetime {
+            return state;
+        }
+        match state {
+            InvoiceState::Empty => InvoiceState::Rejected,
+            InvoiceState::Incomplete => InvoiceState::Expired,
+            state => state,
+        }
+    }
+
+    pub async fn balance(&self, provider_arc: ProviderArc, block: BlockId) -> Result<U256> {
+        let address = self.address.parse::<Address>()?;
+        match &self.token {
+            Some(token) => erc20::balance_of(provider_arc, token.parse()?, address, block).await,
+            None => Ok(provider_arc.get_balance(address).block_id(block).await?),
+        }
     }
 
+    /// Whether the invoice funds are waiting to be, or being, swept.
+    pub fn is_sweep_queued(&self) -> bool {
+        matches!(
+            self.state,
+            InvoiceState::Complete
+                | InvoiceState::Sweeping
+                | InvoiceState::SweepFailed
+                | InvoiceState::SweepDeferred
+        ) && matches!(self.complete_action, InvoiceAction::SendToReceiver)
+    }
+
+    /// Encrypts the invoice private key into a Web3 Secret Storage keystore.
+    pub fn export_keystore(&self, password: &str) -> Result<String> {
+        let wallet = self.signer()?;
+        let directory =
+            std::env::temp_dir().join(format!("paymenator-{:x}", rand::random::<u64>()));
+        std::fs::create_dir(&directory)?;
+
+        let keystore = Self::write_keystore(&directory, wallet, password, &self.address);
+
+        std::fs::remove_dir_all(&directory)?;
+        keystore
+    }
+
+    fn write_keystore(
+        directory: &std::path::Path,
+        wallet: &PrivateKeySigner,
+        password: &str,
+        name: &str,
+    ) -> Result<String> {
+        PrivateKeySigner::encrypt_keystore(
+            directory,
+            &mut rand::thread_rng(),
+            wallet.to_bytes(),
+            password,
+            Some(name),
+        )?;
+        Ok(std::fs::read_to_string(directory.join(name))?)
+    }
+
+    fn signer(&self) -> Result<&PrivateKeySigner> {
+        self.wallet
+            .as_ref()
+            .ok_or_else(|| eyre!("Invoice {} is watch-only", self.address))
+    }
+
+    /// Sweeps the invoice balance to the receiver. Passing the `nonce` of a
+    /// pending sweep replaces it.
     pub async fn send_money_to_receiver(
         &self,
         provider_arc: ProviderArc,
-        max_priority_fee: u128,
+        fees: SweepFees,
+        nonce: Option<u64>,
+        max_allowed_gas: u128,
+    ) -> Result<SweepTransaction> {
+        let nonce = match nonce {
+            Some(nonce) => nonce,
+            None => {
+                provider_arc
+                    .get_transaction_count(self.address.parse()?)
+                    .await?
+            }
+        };
+        match &self.token {
+            Some(token) => {
+                self.send_tokens_to_receiver(
+                    provider_arc,
+                    token.parse()?,
+                    fees,
+                    nonce,
+                    max_allowed_gas,
+                )
+                .await
+            }
+            None => {
+                self.send_eth_to_receiver(provider_arc, fees, nonce, max_allowed_gas)
+                    .await
+            }
+        }
+    }
+
+    /// Replaces the pending transaction with `nonce` by a zero value transfer
+    /// to the invoice wallet itself.
+    pub async fn cancel_sweep(
+        &self,
+        provider_arc: ProviderArc,
+        fees: SweepFees,
+        nonce: u64,
         max_allowed_gas: u128,
-    ) -> Result<TxHash> {
-        let gas_price = provider_arc.get_gas_price().await?;
-        let max_fee_per_gas = gas_price + max_priority_fee;
+    ) -> Result<SweepTransaction> {
+        let wallet = self.signer()?;
+        let gas_limit = 21000;
+        let max_gas_cost = U256::from(gas_limit * fees.max_fee_per_gas);
+        if max_gas_cost > U256::from(max_allowed_gas) {
+            return Err(SweepTooExpensive {
+                cost: max_gas_cost,
+                max_allowed_gas,
+            }
+            .into());
+        }
 
-        let self_balance = provider_arc.get_balance(self.wallet.address()).await?;
         let chain_id = provider_arc.get_chain_id().await?;
-        let nonce = provider_arc
-            .get_transaction_count(self.wallet.address())
+        let built_transaction = TransactionRequest::default()
+            .with_to(wallet.address())
+            .with_value(U256::from(0))
+            .with_max_fee_per_gas(fees.max_fee_per_gas)
+            .with_max_priority_fee_per_gas(fees.max_priority_fee_per_gas)
+            .with_chain_id(chain_id)
+            .with_nonce(nonce)
+            .with_gas_limit(gas_limit)
+            .build(&EthereumWallet::new(wallet.clone()))
             .await?;
+        let tx_hash = provider_arc
+            .send_tx_envelope(built_transaction)
+            .await?
+            .tx_hash()
+            .to_owned();
+        info!(
+            "Cancel transaction hash: {} for {}",
+            tx_hash,
+            wallet.address()
+        );
+        Ok(SweepTransaction {
+            tx_hash,
+            nonce,
+            fees,
+            kind: SweepTransactionKind::Cancel,
+        })
+    }
+
+    async fn send_eth_to_receiver(
+        &self,
+        provider_arc: ProviderArc,
+        fees: SweepFees,
+        nonce: u64,
+        max_allowed_gas: u128,
+    ) -> Result<SweepTransaction> {
+        let wallet = self.signer()?;
+        let max_fee_per_gas = fees.max_fee_per_gas;
+
+        let self_balance = provider_arc.get_balance(wallet.address()).await?;
+        let chain_id = provider_arc.get_chain_id().await?;
 
         let mut transaction_request = TransactionRequest::default()
             .with_to(self.receiver.parse::<Address>()?)
             .with_max_fee_per_gas(max_fee_per_gas)
-            .with_max_priority_fee_per_gas(max_priority_fee)
+            .with_max_priority_fee_per_gas(fees.max_priority_fee_per_gas)
             .with_chain_id(chain_id)
             .with_nonce(nonce)
             .with_value(U256::from(0));
@@ -335,8 +1892,11 @@ impl Invoice {
         let max_gas_cost = U256::from(gas_limit.mul(max_fee_per_gas));
 
         if max_gas_cost > U256::from(max_allowed_gas) {
-            error!("Max gas cost is bigger than maximum gas. Aborting");
-            return Err(eyre!("Max gas cost is bigger than maximum gas. Aborting"));
+            return Err(SweepTooExpensive {
+                cost: max_gas_cost,
+                max_allowed_gas,
+            }
+            .into());
         };
 
         let max_send_amount = if self_balance > max_gas_cost {
@@ -351,7 +1911,7 @@ impl Invoice {
                 .with_value(max_send_amount)
                 .with_gas_limit(gas_limit);
 
-            info!("\n\nAddress: {}", self.wallet.address());
+            info!("\n\nAddress: {}", wallet.address());
             info!("Balance: {}", self_balance);
             info!("Gas price: {}", max_fee_per_gas);
             info!("Gas limit: {}", gas_limit);
@@ -359,7 +1919,7 @@ impl Invoice {
             info!("Sending amount: {}\n\n", max_send_amount);
 
             let built_transaction = transaction_request
-                .build(&EthereumWallet::new(self.wallet.clone()))
+                .build(&EthereumWallet::new(wallet.clone()))
                 .await?;
             let pending_transaction = provider_arc
                 .send_tx_envelope(built_transaction)
@@ -370,20 +1930,289 @@ impl Invoice {
             info!(
                 "Transaction hash: {} for {}",
                 pending_transaction,
-                self.wallet.address()
+                wallet.address()
             );
-            Ok(pending_transaction)
+            Ok(SweepTransaction {
+                tx_hash: pending_transaction,
+                nonce,
+                fees,
+                kind: SweepTransactionKind::Sweep,
+            })
         } else {
             error!(
                 "Insufficient funds to send: {}, {}",
-                self.wallet.address(),
+                wallet.address(),
                 self_balance
             );
             Err(eyre!(
                 "Insufficient funds to send: {}, {}",
-                self.wallet.address(),
+                wallet.address(),
                 self_balance
             ))
         }
     }
+
+    async fn send_tokens_to_receiver(
+        &self,
+        provider_arc: ProviderArc,
+        token: Address,
+        fees: SweepFees,
+        nonce: u64,
+        max_allowed_gas: u128,
+    ) -> Result<SweepTransaction> {
+        let wallet = self.signer()?;
+        let (transaction_request, max_gas_cost) = self
+            .prepare_token_sweep(provider_arc.clone(), token, fees)
+            .await?;
+
+        if max_gas_cost > U256::from(max_allowed_gas) {
+            return Err(SweepTooExpensive {
+                cost: max_gas_cost,
+                max_allowed_gas,
+            }
+            .into());
+        };
+
+        let self_balance = provider_arc.get_balance(wallet.address()).await?;
+        if self_balance < max_gas_cost {
+            error!(
+                "Insufficient funds for gas: {}, {}",
+                wallet.address(),
+                self_balance
+            );
+            return Err(eyre!(
+                "Insufficient funds for gas: {}, {}",
+                wallet.address(),
+                self_balance
+            ));
+        }
+
+        info!("\n\nAddress: {}", wallet.address());
+        info!("Token: {}", token);
+        info!("Balance: {}", self_balance);
+        info!("Estimated max gas cost: {}\n\n", max_gas_cost);
+
+        let built_transaction = transaction_request
+            .with_nonce(nonce)
+            .build(&EthereumWallet::new(wallet.clone()))
+            .await?;
+        let pending_transaction = provider_arc
+            .send_tx_envelope(built_transaction)
+            .await?
+            .with_required_confirmations(2)
+            .tx_hash()
+            .to_owned();
+        info!(
+            "Transaction hash: {} for {}",
+            pending_transaction,
+            wallet.address()
+        );
+        Ok(SweepTransaction {
+            tx_hash: pending_transaction,
+            nonce,
+            fees,
+            kind: SweepTransactionKind::Sweep,
+        })
+    }
+
+    /// Maximum gas cost in wei of sweeping the token balance to the receiver.
+    pub async fn token_sweep_gas_cost(
+        &self,
+        provider_arc: ProviderArc,
+        fees: SweepFees,
+    ) -> Result<U256> {
+        let token = match &self.token {
+            Some(token) => token.parse::<Address>()?,
+            None => return Err(eyre!("Invoice {} is not a token invoice", self.address)),
+        };
+        let (_, max_gas_cost) = self.prepare_token_sweep(provider_arc, token, fees).await?;
+        Ok(max_gas_cost)
+    }
+
+    async fn prepare_token_sweep(
+        &self,
+        provider_arc: ProviderArc,
+        token: Address,
+        fees: SweepFees,
+    ) -> Result<(TransactionRequest, U256)> {
+        let wallet = self.signer()?;
+        let token_balance = erc20::balance_of(
+            provider_arc.clone(),
+            token,
+            wallet.address(),
+            BlockId::latest(),
+        )
+        .await?;
+        if token_balance == U256::from(0) {
+            error!("No tokens to send: {}, {}", wallet.address(), token);
+            return Err(eyre!("No tokens to send: {}, {}", wallet.address(), token));
+        }
+
+        let chain_id = provider_arc.get_chain_id().await?;
+
+        let transaction_request = TransactionRequest::default()
+            .with_from(wallet.address())
+            .with_to(token)
+            .with_input(erc20::transfer_input(
+                self.receiver.parse::<Address>()?,
+                token_balance,
+            ))
+            .with_value(U256::from(0));
+
+        // Estimated without fee fields so that a wallet holding no ETH yet
+        // can still be estimated before its top-up.
+        let gas_limit = provider_arc.estimate_gas(&transaction_request).await?;
+        let max_gas_cost = U256::from(gas_limit.mul(fees.max_fee_per_gas));
+
+        Ok((
+            transaction_request
+                .with_max_fee_per_gas(fees.max_fee_per_gas)
+                .with_max_priority_fee_per_gas(fees.max_priority_fee_per_gas)
+                .with_chain_id(chain_id)
+                .with_gas_limit(gas_limit),
+            max_gas_cost,
+        ))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Marks the sweep failed, alerting the operator when it is given up.
+fn sweep_failed(sweep: &mut Sweep, error: String) {
+    if sweep.failed(error.clone()) {
+        error!(
+            target: "alert",
+            "Sweep of {} given up after {} attempts: {error}",
+            sweep.invoice_address, sweep.attempts
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIFETIME: u64 = 1_000;
+
+    fn invoice(value: u64, confirmations: u64) -> Invoice {
+        Invoice {
+            address: "0x0000000000000000000000000000000000000001".to_string(),
+            wallet: None,
+            receiver: "0x0000000000000000000000000000000000000002".to_string(),
+            mnemonic: None,
+            derivation_index: None,
+            value: U256::from(value),
+            state: InvoiceState::Empty,
+            lifetime: LIFETIME,
+            complete_action: InvoiceAction::Nothing,
+            confirmations,
+            token: None,
+            decimals: 18,
+            top_up_cost: U256::ZERO,
+            scanned_block: 0,
+            pending_payment: None,
+            merchant_id: None,
+            webhook_url: None,
+            fiat: None,
+        }
+    }
+
+    fn payment(block_number: u64, amount: u64) -> Payment {
+        Payment {
+            invoice_address: "0x0000000000000000000000000000000000000001".to_string(),
+            tx_hash: format!("0x{block_number:064x}"),
+            block_number,
+            sender: "0x0000000000000000000000000000000000000003".to_string(),
+            amount: U256::from(amount),
+            log_index: None,
+        }
+    }
+
+    #[test]
+    fn paid_counts_only_confirmed_payments_as_confirmed() {
+        let invoice = invoice(100, 2);
+        let payments = [payment(10, 30), payment(11, 40), payment(12, 50)];
+
+        assert_eq!(
+            invoice.paid(&payments, 12),
+            (U256::from(120), U256::from(70))
+        );
+        assert_eq!(
+            invoice.paid(&payments, 13),
+            (U256::from(120), U256::from(120))
+        );
+        assert_eq!(invoice.paid(&[], 13), (U256::ZERO, U256::ZERO));
+    }
+
+    #[test]
+    fn applies_balances_before_lifetime() {
+        let mut invoice = invoice(100, 2);
+        let cases = [
+            (0, 0, InvoiceState::Empty),
+            (50, 0, InvoiceState::Incomplete),
+            (100, 50, InvoiceState::Confirming),
+            (150, 100, InvoiceState::Complete),
+        ];
+        for (balance, confirmed_balance, expected) in cases {
+            let state = invoice.apply_balances(
+                U256::from(balance),
+                U256::from(confirmed_balance),
+                LIFETIME - 1,
+            );
+            assert_eq!(state.to_int(), expected.to_int());
+            assert_eq!(invoice.state.to_int(), expected.to_int());
+        }
+    }
+
+    #[test]
+    fn expires_once_observed_past_lifetime() {
+        let mut invoice = invoice(100, 2);
+        let cases = [
+            (0, 0, InvoiceState::Rejected),
+            (50, 50, InvoiceState::Expired),
+            (100, 50, InvoiceState::Confirming),
+            (100, 100, InvoiceState::Complete),
+        ];
+        for (balance, confirmed_balance, expected) in cases {
+            let state = invoice.apply_balances(
+                U256::from(balance),
+                U256::from(confirmed_balance),
+                LIFETIME,
+            );
+            assert_eq!(state.to_int(), expected.to_int());
+        }
+    }
+
+    #[test]
+    fn pending_payment_holds_invoice_open() {
+        let mut invoice = invoice(100, 2);
+        invoice.pending_payment = Some(PendingPayment {
+            tx_hash: "0x01".to_string(),
+            amount: U256::from(100),
+        });
+        let state = invoice.apply_balances(U256::ZERO, U256::ZERO, 0);
+        assert!(matches!(state, InvoiceState::PaymentPending));
+        assert!(invoice.pending_payment.is_some());
+
+        let state = invoice.apply_balances(U256::from(100), U256::ZERO, 0);
+        assert!(matches!(state, InvoiceState::Confirming));
+        assert!(invoice.pending_payment.is_none());
+    }
+
+    #[test]
+    fn updates_state_from_payments_at_indexed_block() {
+        let mut invoice = invoice(100, 2);
+        let payments = [payment(10, 100)];
+
+        let state = invoice.update_state_from_payments(&payments, 10, LIFETIME);
+        assert!(matches!(state, InvoiceState::Confirming));
+
+        let state = invoice.update_state_from_payments(&payments, 11, LIFETIME);
+        assert!(matches!(state, InvoiceState::Complete));
+    }
 }
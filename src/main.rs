@@ -1,21 +1,57 @@
+use crate::api_keys::{check_merchant, generate_key, hash_key, parse_scopes};
 use crate::app_state::AppState;
 use crate::controller::{
-    create_invoice, get_invoice_by_action, get_invoice_by_address, get_invoice_by_status,
-    manual_update,
+    cancel_sweep, create_api_key, create_invoice, create_merchant, create_rescan, export_key,
+    get_api_keys, get_invoice_by_action, get_invoice_by_address, get_invoice_by_status,
+    get_merchants, get_metrics, get_rescan, manual_update, revoke_api_key, update_merchant,
 };
+use crate::crypto::KeyRing;
+use crate::fees::Urgency;
+use crate::funding::FundingStation;
+use crate::hd_wallet::HdWallet;
 use crate::invoice_service::InvoiceService;
 use crate::invoices::InvoiceManager;
+use crate::prices::PriceSource;
+use crate::quorum::Quorum;
+use crate::rpc::{FailoverTransport, RpcSettings};
+use crate::sweeps::SweepSettings;
+use actix_web::middleware::from_fn;
 use actix_web::{web, App, HttpServer};
-use diesel::{Connection, PgConnection};
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use log::info;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
 
+mod api_keys;
 mod app_state;
+mod auth;
+mod balances;
 mod controller;
+mod crypto;
+mod dto;
+mod erc20;
+mod fees;
+mod funding;
+mod hd_wallet;
+mod heads;
+mod indexer;
 mod invoice_service;
 mod invoices;
 mod logger;
+mod mempool;
+mod merchants;
+mod metrics;
+mod migrations;
 mod models;
+mod payments;
+mod prices;
+mod quorum;
+mod rescans;
+mod rpc;
 mod schema;
+mod sweeps;
 mod utils;
 
 #[tokio::main]
@@ -24,47 +60,327 @@ async fn main() -> std::io::Result<()> {
     logger::setup_logger("data/log.txt").unwrap();
 
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let connection = PgConnection::establish(&database_url)
+    let pool = Pool::builder()
+        .max_size(
+            std::env::var("DATABASE_POOL_SIZE")
+                .map(|size| size.parse().unwrap())
+                .unwrap_or(10),
+        )
+        .build(ConnectionManager::<PgConnection>::new(&database_url))
         .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
 
-    let invoice_service = InvoiceService::new(connection);
+    let command = std::env::args().nth(1);
+    if let Some("migrate") = command.as_deref() {
+        let failed =
+            |report: eyre::Error| std::io::Error::other(format!("Migration failed: {report}"));
+        let mut connection = pool.get().map_err(std::io::Error::other)?;
+        match std::env::args().nth(2).as_deref() {
+            Some("up") => {
+                for version in migrations::run_pending(&mut connection).map_err(failed)? {
+                    info!("Applied migration {version}");
+                }
+            }
+            Some("down") => {
+                let version = migrations::revert_last(&mut connection).map_err(failed)?;
+                info!("Reverted migration {version}");
+            }
+            Some("status") => {
+                for (name, applied) in migrations::status(&mut connection).map_err(failed)? {
+                    info!("{} {name}", if applied { "applied" } else { "pending" });
+                }
+            }
+            subcommand => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Unknown migrate subcommand {}\nUsage: paymenator migrate up|down|status",
+                        subcommand.unwrap_or("(none)")
+                    ),
+                ))
+            }
+        }
+        return Ok(());
+    }
+    if std::env::var("RUN_MIGRATIONS").is_ok_and(|run| run.parse().unwrap()) {
+        let mut connection = pool.get().map_err(std::io::Error::other)?;
+        let applied = migrations::run_pending(&mut connection)
+            .map_err(|report| std::io::Error::other(format!("Migration failed: {report}")))?;
+        for version in applied {
+            info!("Applied migration {version}");
+        }
+    }
+
+    let hd_wallet = Arc::new(HdWallet::from_env().unwrap());
+    let is_watch_only = hd_wallet.is_watch_only();
+    let key_ring = KeyRing::from_env().unwrap();
+    let invoice_service = InvoiceService::new(pool, hd_wallet.clone(), key_ring);
+
+    if let Some("api-key") = command.as_deref() {
+        let usage = |error: String| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{error}\nUsage: paymenator api-key issue <name> [--merchant <id>] <scope>...|list|revoke <id>"
+                ),
+            )
+        };
+        let failed = |report: eyre::Error| {
+            std::io::Error::other(format!("API key command failed: {report}"))
+        };
+        match std::env::args().nth(2).as_deref() {
+            Some("issue") => {
+                let name = std::env::args()
+                    .nth(3)
+                    .ok_or_else(|| usage("Key name is required".to_string()))?;
+                let mut args = std::env::args().skip(4).collect::<Vec<_>>();
+                let merchant_id = match args.iter().position(|arg| arg == "--merchant") {
+                    Some(position) => {
+                        let merchant_id = args
+                            .get(position + 1)
+                            .ok_or_else(|| usage("Merchant id is required".to_string()))?;
+                        let merchant_id = merchant_id
+                            .parse()
+                            .map_err(|_| usage(format!("Invalid merchant id {merchant_id}")))?;
+                        args.drain(position..=position + 1);
+                        Some(merchant_id)
+                    }
+                    None => None,
+                };
+                let scopes = parse_scopes(&args).map_err(|report| usage(report.to_string()))?;
+                check_merchant(&scopes, merchant_id).map_err(|report| usage(report.to_string()))?;
+                let key = generate_key();
+                let api_key = invoice_service
+                    .create_api_key(name, &scopes, merchant_id, hash_key(&key))
+                    .map_err(failed)?;
+                info!(target: "audit", "API key {} ({}) issued from CLI", api_key.id, api_key.name);
+                println!("{key}");
+            }
+            Some("list") => {
+                for api_key in invoice_service.list_api_keys().map_err(failed)? {
+                    let scopes = api_key
+                        .scopes
+                        .iter()
+                        .map(|scope| scope.as_str())
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    let status = if api_key.revoked_at.is_some() {
+                        "revoked"
+                    } else {
+                        "active"
+                    };
+                    info!("{} {} {scopes} {status}", api_key.id, api_key.name);
+                }
+            }
+            Some("revoke") => {
+                let id = std::env::args()
+                    .nth(3)
+                    .ok_or_else(|| usage("Key id is required".to_string()))?;
+                let key_id = id
+                    .parse()
+                    .map_err(|_| usage(format!("Invalid key id {id}")))?;
+                if invoice_service.revoke_api_key(key_id).map_err(failed)? {
+                    info!(target: "audit", "API key {id} revoked from CLI");
+                } else {
+                    info!("API key {id} does not exist or is already revoked");
+                }
+            }
+            subcommand => {
+                return Err(usage(format!(
+                    "Unknown api-key subcommand {}",
+                    subcommand.unwrap_or("(none)")
+                )))
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some("encrypt-mnemonics") = command.as_deref() {
+        let updated = invoice_service.encrypt_mnemonics().unwrap();
+        info!("Encrypted {updated} invoice mnemonics");
+        return Ok(());
+    }
+
+    let funding_station = std::env::var("FUNDING_PRIVATE_KEY")
+        .ok()
+        .map(|private_key| {
+            FundingStation::new(private_key).expect("FUNDING_PRIVATE_KEY is invalid")
+        });
+
+    let rpc_settings = RpcSettings {
+        urls: std::env::var("RPC_URL")
+            .expect("RPC_URL is not present")
+            .split(',')
+            .map(|url| url.trim().to_string())
+            .collect(),
+        timeout: Duration::from_secs(
+            std::env::var("RPC_TIMEOUT")
+                .map(|timeout| timeout.parse().unwrap())
+                .unwrap_or(10),
+        ),
+        max_retries: std::env::var("RPC_MAX_RETRIES")
+            .map(|retries| retries.parse().unwrap())
+            .unwrap_or(3),
+        retry_delay: Duration::from_millis(
+            std::env::var("RPC_RETRY_DELAY")
+                .map(|delay| delay.parse().unwrap())
+                .unwrap_or(500),
+        ),
+    };
+    let quorum = std::env::var("RPC_QUORUM").ok().map(|required| {
+        let min_value = std::env::var("QUORUM_MIN_VALUE")
+            .map(|value| value.parse().unwrap())
+            .unwrap_or_default();
+        Quorum::new(&rpc_settings, required.parse().unwrap(), min_value)
+            .expect("RPC_QUORUM is invalid")
+    });
+    let provider = FailoverTransport::new(rpc_settings)
+        .expect("RPC_URL is invalid")
+        .into_provider();
 
     let invoice_manager = InvoiceManager::new(
-        std::env::var("RPC_URL").expect("RPC_URL is not present"),
-        invoice_service,
-        std::env::var("MAX_ALLOWED_GAS")
-            .expect("MAX_ALLOWED_GAS is not present")
-            .parse()
-            .unwrap(),
-        std::env::var("MAX_PRIORITY_FEE")
-            .expect("MAX_PRIORITY_FEE is not present")
-            .parse()
-            .unwrap(),
+        Arc::new(provider),
+        invoice_service.clone(),
+        SweepSettings {
+            max_allowed_gas: std::env::var("MAX_ALLOWED_GAS")
+                .expect("MAX_ALLOWED_GAS is not present")
+                .parse()
+                .unwrap(),
+            max_priority_fee: std::env::var("MAX_PRIORITY_FEE")
+                .expect("MAX_PRIORITY_FEE is not present")
+                .parse()
+                .unwrap(),
+            stuck_timeout: Duration::from_secs(
+                std::env::var("SWEEP_STUCK_TIMEOUT")
+                    .map(|timeout| timeout.parse().unwrap())
+                    .unwrap_or(600),
+            ),
+            urgency: std::env::var("SWEEP_URGENCY")
+                .map(|urgency| urgency.parse().unwrap())
+                .unwrap_or(Urgency::Medium),
+            defer_deadline: Duration::from_secs(
+                std::env::var("SWEEP_DEFER_DEADLINE")
+                    .map(|deadline| deadline.parse().unwrap())
+                    .unwrap_or(86400),
+            ),
+        },
+        funding_station,
+        hd_wallet,
+        std::env::var("REQUIRED_CONFIRMATIONS")
+            .map(|confirmations| confirmations.parse().unwrap())
+            .unwrap_or(2),
+        Duration::from_secs(
+            std::env::var("POLL_INTERVAL")
+                .map(|interval| interval.parse().unwrap())
+                .unwrap_or(15),
+        ),
     )
     .await;
 
+    if let Some("rescan") = command.as_deref() {
+        let usage = |error: String| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{error}\nUsage: paymenator rescan [<from_block> <to_block>]"),
+            )
+        };
+        let range = std::env::args().nth(2).zip(std::env::args().nth(3));
+        if let Some((from_block, to_block)) = range {
+            let from_block = from_block
+                .parse()
+                .map_err(|_| usage(format!("Invalid from_block {from_block}")))?;
+            let to_block = to_block
+                .parse()
+                .map_err(|_| usage(format!("Invalid to_block {to_block}")))?;
+            let rescan = invoice_manager
+                .lock()
+                .await
+                .create_rescan(from_block, to_block)
+                .await
+                .map_err(|report| usage(report.to_string()))?;
+            info!("Created rescan {}", rescan.id);
+        }
+        InvoiceManager::run_rescans(invoice_manager)
+            .await
+            .map_err(|report| std::io::Error::other(format!("Rescan failed: {report}")))?;
+        return Ok(());
+    }
+
+    // Runs until the process exits.
+    if let Ok(ws_url) = std::env::var("WS_RPC_URL") {
+        InvoiceManager::start_head_listener(invoice_manager.clone(), ws_url).await;
+    }
+
+    if let Some(quorum) = quorum {
+        InvoiceManager::set_quorum(invoice_manager.clone(), quorum).await;
+    }
+
+    if let Some(price_source) = PriceSource::from_env().expect("PRICE_SOURCE is invalid") {
+        let price_assets = prices::price_assets_from_env().expect("PRICE_ASSETS is invalid");
+        InvoiceManager::set_price_source(invoice_manager.clone(), price_source, price_assets).await;
+    }
+
+    if std::env::var("WATCH_MEMPOOL").is_ok_and(|watch| watch.parse().unwrap()) {
+        InvoiceManager::watch_mempool(invoice_manager.clone()).await;
+    }
+
+    if let Some("signer") = command.as_deref() {
+        if is_watch_only {
+            panic!("signer requires MASTER_MNEMONIC or MASTER_XPRV");
+        }
+        return run_signer(invoice_manager).await;
+    }
+
     let invoicemgr_handler = InvoiceManager::start_loop(invoice_manager.clone());
     let invoice_manager_clone = Arc::clone(&invoice_manager);
+    let admin_token = std::env::var("ADMIN_TOKEN").ok();
 
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(AppState {
                 invoice_manager: Arc::clone(&invoice_manager_clone),
+                invoice_service: invoice_service.clone(),
+                admin_token: admin_token.clone(),
             }))
-            .route(
-                "/get_by_status/{status}",
-                web::get().to(get_invoice_by_status),
+            .service(
+                web::resource("/get_by_status/{status}")
+                    .wrap(from_fn(auth::require_invoices_read))
+                    .route(web::get().to(get_invoice_by_status)),
+            )
+            .service(
+                web::resource("/get_by_action/{action}")
+                    .wrap(from_fn(auth::require_invoices_read))
+                    .route(web::get().to(get_invoice_by_action)),
+            )
+            .service(
+                web::resource("/get_by_address/{address}")
+                    .wrap(from_fn(auth::require_invoices_read))
+                    .route(web::get().to(get_invoice_by_address)),
+            )
+            .service(
+                web::resource("/manual_check/{address}")
+                    .wrap(from_fn(auth::require_invoices_create))
+                    .route(web::get().to(manual_update)),
             )
-            .route(
-                "/get_by_action/{action}",
-                web::get().to(get_invoice_by_action),
+            .service(
+                web::resource("/create_invoice")
+                    .wrap(from_fn(auth::require_invoices_create))
+                    .route(web::post().to(create_invoice)),
             )
-            .route(
-                "/get_by_address/{address}",
-                web::get().to(get_invoice_by_address),
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(auth::require_admin))
+                    .route("/export_key/{address}", web::post().to(export_key))
+                    .route("/cancel_sweep/{address}", web::post().to(cancel_sweep))
+                    .route("/rescan", web::post().to(create_rescan))
+                    .route("/rescan/{id}", web::get().to(get_rescan))
+                    .route("/api_keys", web::post().to(create_api_key))
+                    .route("/api_keys", web::get().to(get_api_keys))
+                    .route("/api_keys/{id}", web::delete().to(revoke_api_key))
+                    .route("/merchants", web::post().to(create_merchant))
+                    .route("/merchants", web::get().to(get_merchants))
+                    .route("/merchants/{id}", web::put().to(update_merchant)),
             )
-            .route("/manual_check/{address}", web::get().to(manual_update))
-            .route("/create_invoice", web::post().to(create_invoice))
+            .route("/metrics", web::get().to(get_metrics))
     })
     .bind(("127.0.0.1", 8080))?
     .run()
@@ -74,3 +390,12 @@ async fn main() -> std::io::Result<()> {
     invoicemgr_handler.await?;
     Ok(())
 }
+
+async fn run_signer(invoice_manager: Arc<Mutex<InvoiceManager>>) -> std::io::Result<()> {
+    let signer_handler = InvoiceManager::start_signer_loop(invoice_manager.clone());
+    tokio::signal::ctrl_c().await?;
+
+    InvoiceManager::stop_loop(invoice_manager.clone()).await;
+    signer_handler.await?;
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use crate::erc20::IERC20;
+use crate::indexer::WatchedAddresses;
+use crate::invoices::ProviderArc;
+use crate::payments::PendingPayment;
+use alloy::primitives::Address;
+use alloy::providers::Provider;
+use alloy::rpc::types::Transaction;
+use alloy::sol_types::SolCall;
+use eyre::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// `txpool_content` response, transactions by sender and nonce.
+#[derive(Debug, Deserialize)]
+struct TxpoolContent {
+    pending: HashMap<Address, HashMap<String, Transaction>>,
+}
+
+/// Finds unmined ETH transfers and direct token `transfer` calls to watched
+/// addresses in the node transaction pool. Only the first transfer seen for
+/// each invoice is kept.
+pub async fn pending_payments(
+    provider_arc: ProviderArc,
+    watched: &WatchedAddresses,
+) -> Result<HashMap<String, PendingPayment>> {
+    let content: TxpoolContent = provider_arc.client().request("txpool_content", ()).await?;
+    Ok(match_pending(&content, watched))
+}
+
+fn match_pending(
+    content: &TxpoolContent,
+    watched: &WatchedAddresses,
+) -> HashMap<String, PendingPayment> {
+    let mut pending = HashMap::new();
+    for transaction in content.pending.values().flat_map(HashMap::values) {
+        let Some(to) = transaction.to else { continue };
+        let payment = if watched.is_watched(&to) && !transaction.value.is_zero() {
+            Some((to, transaction.value))
+        } else {
+            IERC20::transferCall::abi_decode(&transaction.input, true)
+                .ok()
+                .filter(|call| watched.is_watched_token(&to, &call.to) && !call.value.is_zero())
+                .map(|call| (call.to, call.value))
+        };
+        if let Some((address, amount)) = payment {
+            pending
+                .entry(address.to_string())
+                .or_insert(PendingPayment {
+                    tx_hash: transaction.hash.to_string(),
+                    amount,
+                });
+        }
+    }
+    pending
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::U256;
+
+    /// `txpool_content` of a node with ETH and token transfers to watched and
+    /// unknown addresses.
+    const TXPOOL_CONTENT: &str = include_str!("fixtures/txpool_content.json");
+
+    #[test]
+    fn finds_transfers_to_watched_addresses() {
+        let invoice = Address::repeat_byte(0x11);
+        let token_invoice = Address::repeat_byte(0x22);
+        let token = Address::repeat_byte(0x33);
+        let watched = WatchedAddresses::new(vec![
+            (invoice.to_string(), None),
+            (token_invoice.to_string(), Some(token.to_string())),
+        ])
+        .unwrap();
+        let content: TxpoolContent = serde_json::from_str(TXPOOL_CONTENT).unwrap();
+
+        let pending = match_pending(&content, &watched);
+        assert_eq!(pending.len(), 2);
+        let payment = &pending[&invoice.to_string()];
+        assert_eq!(payment.tx_hash, format!("0x{:064x}", 1));
+        assert_eq!(payment.amount, U256::from(10u64.pow(18)));
+        let payment = &pending[&token_invoice.to_string()];
+        assert_eq!(payment.tx_hash, format!("0x{:064x}", 2));
+        assert_eq!(payment.amount, U256::from(1_000));
+    }
+}
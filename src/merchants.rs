@@ -0,0 +1,35 @@
+use crate::invoices::InvoiceAction;
+
+/// Invoices a caller may see. Merchant API keys see only their own merchant,
+/// admin keys see all invoices.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MerchantScope {
+    All,
+    Merchant(i32),
+}
+
+impl MerchantScope {
+    pub fn merchant_id(&self) -> Option<i32> {
+        match self {
+            Self::All => None,
+            Self::Merchant(id) => Some(*id),
+        }
+    }
+}
+
+/// Used for invoices created by the merchant that leave these out.
+#[derive(Clone, Default)]
+pub struct MerchantDefaults {
+    pub receiver: Option<String>,
+    /// Seconds.
+    pub lifetime: Option<u64>,
+    pub complete_action: Option<InvoiceAction>,
+    pub webhook_url: Option<String>,
+}
+
+pub struct Merchant {
+    pub id: i32,
+    pub name: String,
+    pub defaults: MerchantDefaults,
+    pub created_at: u64,
+}
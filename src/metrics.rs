@@ -0,0 +1,43 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub static QUORUM_DISAGREEMENTS: Counter = Counter::new(
+    "paymenator_quorum_disagreements_total",
+    "Payment verifications where RPC endpoints returned different answers",
+);
+
+const COUNTERS: [&Counter; 1] = [&QUORUM_DISAGREEMENTS];
+
+/// Renders all counters in the Prometheus text format.
+pub fn render() -> String {
+    COUNTERS
+        .iter()
+        .map(|counter| {
+            format!(
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}\n",
+                name = counter.name,
+                help = counter.help,
+                value = counter.value.load(Ordering::Relaxed)
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,43 @@
+use diesel::migration::{Migration, MigrationSource};
+use diesel::pg::Pg;
+use diesel::PgConnection;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use eyre::{eyre, Result};
+
+/// Migrations from `migrations/`, compiled into the binary.
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+
+/// Applies all pending migrations and returns their versions.
+pub fn run_pending(connection: &mut PgConnection) -> Result<Vec<String>> {
+    Ok(connection
+        .run_pending_migrations(MIGRATIONS)
+        .map_err(|error| eyre!(error))?
+        .into_iter()
+        .map(|version| version.to_string())
+        .collect())
+}
+
+/// Reverts the latest applied migration and returns its version.
+pub fn revert_last(connection: &mut PgConnection) -> Result<String> {
+    Ok(connection
+        .revert_last_migration(MIGRATIONS)
+        .map_err(|error| eyre!(error))?
+        .to_string())
+}
+
+/// Names of all embedded migrations, oldest first, with whether they are
+/// applied.
+pub fn status(connection: &mut PgConnection) -> Result<Vec<(String, bool)>> {
+    let applied = connection
+        .applied_migrations()
+        .map_err(|error| eyre!(error))?;
+    let migrations: Vec<Box<dyn Migration<Pg>>> =
+        MigrationSource::<Pg>::migrations(&MIGRATIONS).map_err(|error| eyre!(error))?;
+    Ok(migrations
+        .iter()
+        .map(|migration| {
+            let name = migration.name();
+            (name.to_string(), applied.contains(&name.version()))
+        })
+        .collect())
+}
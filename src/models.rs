@@ -1,14 +1,124 @@
+use bigdecimal::BigDecimal;
 use diesel::prelude::*;
 
 #[derive(Queryable, Selectable, Insertable)]
 #[diesel(table_name = crate::schema::invoice)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
-pub struct Invoice{
+pub struct Invoice {
     pub address: String,
     pub receiver: String,
-    pub mnemonic: String,
+    pub mnemonic: Option<String>,
     pub state: i32,
-    pub value: f64,
+    pub value: BigDecimal,
     pub lifetime: i32,
-    pub complete_action: i32
+    pub complete_action: i32,
+    pub token: Option<String>,
+    pub decimals: i32,
+    pub top_up_cost: BigDecimal,
+    pub derivation_index: Option<i32>,
+    pub confirmations: i32,
+    pub scanned_block: i64,
+    pub pending_tx_hash: Option<String>,
+    pub pending_amount: Option<BigDecimal>,
+    pub merchant_id: Option<i32>,
+    pub webhook_url: Option<String>,
+    pub fiat_amount: Option<BigDecimal>,
+    pub fiat_currency: Option<String>,
+    pub exchange_rate: Option<BigDecimal>,
+    pub rate_timestamp: Option<i64>,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::payments)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Payment {
+    pub invoice_address: String,
+    pub tx_hash: String,
+    pub block_number: i64,
+    pub sender: String,
+    pub amount: BigDecimal,
+    pub log_index: Option<i32>,
+}
+
+#[derive(Queryable, Selectable, Insertable, AsChangeset)]
+#[diesel(table_name = crate::schema::sweeps)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_null = true)]
+pub struct Sweep {
+    pub invoice_address: String,
+    pub tx_hash: Option<String>,
+    pub nonce: Option<i64>,
+    pub max_fee_per_gas: Option<BigDecimal>,
+    pub max_priority_fee_per_gas: Option<BigDecimal>,
+    pub status: i32,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: i64,
+    pub updated_at: i64,
+    pub submitted_at: i64,
+    pub cancel_requested: bool,
+    pub deadline: Option<i64>,
+    pub alerted: bool,
+    pub top_up_tx_hash: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::sweep_transactions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct SweepTransaction {
+    pub invoice_address: String,
+    pub tx_hash: String,
+    pub nonce: i64,
+    pub max_fee_per_gas: BigDecimal,
+    pub max_priority_fee_per_gas: BigDecimal,
+    pub kind: i32,
+    pub created_at: i64,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::indexed_blocks)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct IndexedBlock {
+    pub number: i64,
+    pub hash: String,
+}
+
+#[derive(Queryable, Selectable, AsChangeset)]
+#[diesel(table_name = crate::schema::rescans)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Rescan {
+    pub id: i32,
+    pub from_block: i64,
+    pub to_block: i64,
+    pub next_block: i64,
+    pub status: i32,
+    pub missed_payments: i32,
+    pub repaired_invoices: i32,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::api_keys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    pub revoked_at: Option<i64>,
+    pub merchant_id: Option<i32>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::merchants)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Merchant {
+    pub id: i32,
+    pub name: String,
+    pub receiver: Option<String>,
+    pub lifetime: Option<i32>,
+    pub complete_action: Option<i32>,
+    pub webhook_url: Option<String>,
+    pub created_at: i64,
 }
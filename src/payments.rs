@@ -0,0 +1,19 @@
+use alloy::primitives::U256;
+
+/// Incoming transfer to an invoice wallet.
+pub struct Payment {
+    pub invoice_address: String,
+    pub tx_hash: String,
+    pub block_number: u64,
+    pub sender: String,
+    pub amount: U256,
+    /// `None` for native ETH transfers, which emit no log.
+    pub log_index: Option<u64>,
+}
+
+/// Transfer to an invoice wallet seen in the mempool but not mined yet.
+#[derive(Clone, PartialEq)]
+pub struct PendingPayment {
+    pub tx_hash: String,
+    pub amount: U256,
+}
@@ -0,0 +1,336 @@
+use crate::utils::{numeric_to_u256, InvalidRequest};
+use alloy::primitives::{Address, U256};
+use alloy::transports::http::reqwest::Client;
+use bigdecimal::num_bigint::Sign;
+use bigdecimal::{BigDecimal, RoundingMode};
+use eyre::{eyre, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+const DEFAULT_FEED_POINTER: &str = "/data/amount";
+const FEED_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where exchange rates come from. Prices are of one whole coin or token,
+/// keyed by upper case `<asset>/<currency>`, like `ETH/USD`.
+pub enum PriceSource {
+    /// JSON feed at a URL with `{asset}` and `{currency}` placeholders. The
+    /// price is read at a JSON pointer of the response.
+    Http {
+        client: Client,
+        url: String,
+        pointer: String,
+    },
+    /// JSON object of prices by `<asset>/<currency>`, read on every quote.
+    File(PathBuf),
+    /// Prices that never change, for tests.
+    Fixed(HashMap<String, BigDecimal>),
+}
+
+/// The price source could not be read, answered with 503 Service Unavailable.
+#[derive(thiserror::Error, Debug)]
+#[error("Price source unavailable: {0}")]
+pub struct PriceSourceUnavailable(pub eyre::Error);
+
+/// Fiat amount of an invoice and the rate its value was quoted at. The rate
+/// is locked for the invoice lifetime.
+#[derive(Clone, Debug)]
+pub struct FiatQuote {
+    pub amount: BigDecimal,
+    pub currency: String,
+    /// Fiat price of one whole coin or token.
+    pub rate: BigDecimal,
+    pub timestamp: u64,
+}
+
+impl FiatQuote {
+    /// Amount due in base units, rounded up to a whole base unit.
+    pub fn base_units(&self, decimals: u8) -> Result<U256> {
+        let unit = BigDecimal::from_str(&format!("1e{decimals}"))?;
+        let value = (&self.amount * unit / &self.rate).with_scale_round(0, RoundingMode::Up);
+        numeric_to_u256(value)
+    }
+}
+
+impl PriceSource {
+    /// Reads `PRICE_SOURCE` and `PRICE_FEED_POINTER`. Returns `None` if
+    /// `PRICE_SOURCE` is not set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(source) = std::env::var("PRICE_SOURCE") else {
+            return Ok(None);
+        };
+        Self::parse(source, std::env::var("PRICE_FEED_POINTER").ok()).map(Some)
+    }
+
+    /// Parses a source given as `http(s)://...`, `file:<path>` or
+    /// `fixed:<asset>/<currency>=<price>,...`.
+    fn parse(source: String, pointer: Option<String>) -> Result<Self> {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            return Ok(Self::Http {
+                client: Client::builder().timeout(FEED_TIMEOUT).build()?,
+                url: source,
+                pointer: pointer.unwrap_or(DEFAULT_FEED_POINTER.to_string()),
+            });
+        }
+        if let Some(path) = source.strip_prefix("file:") {
+            return Ok(Self::File(PathBuf::from(path)));
+        }
+        if let Some(prices) = source.strip_prefix("fixed:") {
+            return Ok(Self::Fixed(
+                prices
+                    .split(',')
+                    .map(|price| {
+                        let (pair, price) = price
+                            .split_once('=')
+                            .ok_or_else(|| eyre!("Price must be <asset>/<currency>=<price>"))?;
+                        Ok((pair.trim().to_uppercase(), parse_rate(price.trim())?))
+                    })
+                    .collect::<Result<_>>()?,
+            ));
+        }
+        Err(eyre!("Unknown PRICE_SOURCE {source}"))
+    }
+
+    /// Quotes a fiat amount given as `"<decimal number> <currency>"` in
+    /// `asset`. Fails with [`InvalidRequest`] for a malformed amount or a pair
+    /// the source has no price for, and with [`PriceSourceUnavailable`] when
+    /// the source can not be read.
+    pub async fn quote(&self, fiat_amount: &str, asset: &str) -> Result<FiatQuote> {
+        let invalid_amount = || {
+            InvalidRequest(format!(
+                "Fiat amount must be \"<number> <currency>\", got \"{fiat_amount}\""
+            ))
+        };
+        let (amount, currency) = fiat_amount
+            .trim()
+            .split_once(' ')
+            .ok_or_else(invalid_amount)?;
+        let amount = BigDecimal::from_str(amount).map_err(|_| invalid_amount())?;
+        if amount.sign() != Sign::Plus {
+            return Err(InvalidRequest("Fiat amount must be positive".to_string()).into());
+        }
+        let currency = currency.trim().to_uppercase();
+        let rate = self.rate(&asset.to_uppercase(), &currency).await?;
+
+        Ok(FiatQuote {
+            amount,
+            currency,
+            rate,
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs(),
+        })
+    }
+
+    async fn rate(&self, asset: &str, currency: &str) -> Result<BigDecimal> {
+        let pair = format!("{asset}/{currency}");
+        match self {
+            Self::Http {
+                client,
+                url,
+                pointer,
+            } => {
+                let url = url
+                    .replace("{asset}", asset)
+                    .replace("{currency}", currency);
+                let response = client.get(&url).send().await.map_err(unavailable)?;
+                // Feeds answer pairs they do not know with a client error.
+                if response.status().is_client_error() {
+                    return Err(
+                        InvalidRequest(format!("Price feed has no price for {pair}")).into(),
+                    );
+                }
+                let response: Value = response
+                    .error_for_status()
+                    .map_err(unavailable)?
+                    .json()
+                    .await
+                    .map_err(unavailable)?;
+                let price = response.pointer(pointer).ok_or_else(|| {
+                    InvalidRequest(format!("Price feed response for {pair} has no {pointer}"))
+                })?;
+                json_number(price)
+                    .and_then(|price| parse_rate(&price))
+                    .map_err(|report| PriceSourceUnavailable(report).into())
+            }
+            Self::File(path) => {
+                let prices: HashMap<String, Value> = std::fs::read_to_string(path)
+                    .map_err(eyre::Error::from)
+                    .and_then(|prices| Ok(serde_json::from_str(&prices)?))
+                    .map_err(PriceSourceUnavailable)?;
+                let price = prices.get(&pair).ok_or_else(|| {
+                    InvalidRequest(format!("{} has no price for {pair}", path.display()))
+                })?;
+                json_number(price)
+                    .and_then(|price| parse_rate(&price))
+                    .map_err(|report| PriceSourceUnavailable(report).into())
+            }
+            Self::Fixed(prices) => prices
+                .get(&pair)
+                .cloned()
+                .ok_or_else(|| InvalidRequest(format!("No fixed price for {pair}")).into()),
+        }
+    }
+}
+
+/// Reads `PRICE_ASSETS`, given as `<token address>=<asset>,...`, for tokens
+/// whose `symbol()` is missing, not a string or not the asset the price source
+/// knows them as.
+pub fn price_assets_from_env() -> Result<HashMap<Address, String>> {
+    match std::env::var("PRICE_ASSETS") {
+        Ok(assets) => parse_price_assets(&assets),
+        Err(_) => Ok(HashMap::new()),
+    }
+}
+
+fn parse_price_assets(assets: &str) -> Result<HashMap<Address, String>> {
+    assets
+        .split(',')
+        .map(|asset| {
+            let (token, asset) = asset
+                .split_once('=')
+                .ok_or_else(|| eyre!("Price asset must be <token address>=<asset>"))?;
+            Ok((token.trim().parse()?, asset.trim().to_uppercase()))
+        })
+        .collect()
+}
+
+fn unavailable(error: impl Into<eyre::Error>) -> PriceSourceUnavailable {
+    PriceSourceUnavailable(error.into())
+}
+
+/// Feeds give prices either as JSON numbers or as strings.
+fn json_number(value: &Value) -> Result<String> {
+    match value {
+        Value::String(price) => Ok(price.clone()),
+        Value::Number(price) => Ok(price.to_string()),
+        _ => Err(eyre!("Price {value} is not a number")),
+    }
+}
+
+fn parse_rate(price: &str) -> Result<BigDecimal> {
+    let rate = BigDecimal::from_str(price)?;
+    if rate.sign() != Sign::Plus {
+        return Err(eyre!("Price {price} must be positive"));
+    }
+    Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(amount: &str, rate: &str) -> FiatQuote {
+        FiatQuote {
+            amount: BigDecimal::from_str(amount).unwrap(),
+            currency: "USD".to_string(),
+            rate: BigDecimal::from_str(rate).unwrap(),
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn base_units_round_up_to_whole_base_unit() {
+        assert_eq!(
+            quote("25", "2500").base_units(18).unwrap(),
+            U256::from(10_000_000_000_000_000u64)
+        );
+        // 10 / 3 USDT is 3.333333 and a third base units.
+        assert_eq!(
+            quote("10", "3").base_units(6).unwrap(),
+            U256::from(3_333_334)
+        );
+        assert_eq!(
+            quote("19.99", "1").base_units(6).unwrap(),
+            U256::from(19_990_000)
+        );
+        assert_eq!(
+            quote("0.000001", "1000").base_units(0).unwrap(),
+            U256::from(1)
+        );
+    }
+
+    #[test]
+    fn parses_fixed_prices() {
+        let source =
+            PriceSource::parse("fixed:eth/usd=2500.5, USDT/EUR=0.9".to_string(), None).unwrap();
+        let PriceSource::Fixed(prices) = source else {
+            panic!("Expected fixed prices");
+        };
+        assert_eq!(prices.len(), 2);
+        assert_eq!(prices["ETH/USD"], BigDecimal::from_str("2500.5").unwrap());
+        assert_eq!(prices["USDT/EUR"], BigDecimal::from_str("0.9").unwrap());
+    }
+
+    #[test]
+    fn rejects_invalid_fixed_prices() {
+        for source in [
+            "fixed:ETH/USD",
+            "fixed:ETH/USD=abc",
+            "fixed:ETH/USD=0",
+            "ftp://prices",
+        ] {
+            assert!(PriceSource::parse(source.to_string(), None).is_err());
+        }
+    }
+
+    #[test]
+    fn parses_file_and_http_sources() {
+        let source = PriceSource::parse("file:data/prices.json".to_string(), None).unwrap();
+        assert!(
+            matches!(source, PriceSource::File(path) if path.to_str() == Some("data/prices.json"))
+        );
+
+        let source = PriceSource::parse("https://prices.test/{asset}".to_string(), None).unwrap();
+        assert!(
+            matches!(source, PriceSource::Http { pointer, .. } if pointer == DEFAULT_FEED_POINTER)
+        );
+
+        let source =
+            PriceSource::parse("http://prices.test".to_string(), Some("/price".to_string()))
+                .unwrap();
+        assert!(matches!(source, PriceSource::Http { pointer, .. } if pointer == "/price"));
+    }
+
+    #[test]
+    fn parses_price_assets() {
+        let assets = parse_price_assets(
+            "0x9f8F72aA9304c8B593d555F12eF6589cC3A579A2=mkr, 0xdAC17F958D2ee523a2206206994597C13D831ec7=USDT",
+        )
+        .unwrap();
+        let maker: Address = "0x9f8F72aA9304c8B593d555F12eF6589cC3A579A2"
+            .parse()
+            .unwrap();
+        assert_eq!(assets[&maker], "MKR");
+        assert_eq!(assets.len(), 2);
+
+        assert!(parse_price_assets("MKR").is_err());
+        assert!(parse_price_assets("0x1234=MKR").is_err());
+    }
+
+    #[tokio::test]
+    async fn quotes_from_fixed_prices() {
+        let source = PriceSource::parse("fixed:ETH/USD=2000".to_string(), None).unwrap();
+        let quote = source.quote("50 usd", "eth").await.unwrap();
+        assert_eq!(quote.currency, "USD");
+        assert_eq!(quote.rate, BigDecimal::from(2000));
+        assert_eq!(
+            quote.base_units(18).unwrap(),
+            U256::from(25_000_000_000_000_000u64)
+        );
+
+        for fiat_value in ["50 EUR", "-1 USD", "50", "abc USD"] {
+            let report = source.quote(fiat_value, "ETH").await.unwrap_err();
+            assert!(report.is::<InvalidRequest>(), "{fiat_value}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unreadable_price_files_are_unavailable() {
+        let source = PriceSource::parse("file:/nonexistent/prices.json".to_string(), None).unwrap();
+        let report = source.quote("50 USD", "ETH").await.unwrap_err();
+        assert!(report.is::<PriceSourceUnavailable>());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::indexer;
+use crate::invoices::{Invoice, ProviderArc};
+use crate::metrics;
+use crate::rpc::{FailoverTransport, RpcSettings};
+use alloy::eips::BlockId;
+use alloy::primitives::{B256, U256};
+use eyre::{eyre, Result};
+use futures_util::future::join_all;
+use log::{error, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Verifies payments of high-value invoices against several RPC endpoints
+/// before they complete.
+pub struct Quorum {
+    providers: Vec<(String, ProviderArc)>,
+    /// Endpoints that must return the same answer.
+    required: usize,
+    /// Invoices worth less, in base units, are not verified.
+    min_value: U256,
+}
+
+impl Quorum {
+    pub fn new(settings: &RpcSettings, required: usize, min_value: U256) -> Result<Self> {
+        if required == 0 || required > settings.urls.len() {
+            return Err(eyre!(
+                "Quorum of {required} needs between 1 and {} RPC URLs",
+                settings.urls.len()
+            ));
+        }
+        let providers = settings
+            .urls
+            .iter()
+            .map(|url| {
+                let transport = FailoverTransport::new(RpcSettings {
+                    urls: vec![url.clone()],
+                    ..settings.clone()
+                })?;
+                Ok((url.clone(), Arc::new(transport.into_provider())))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            providers,
+            required,
+            min_value,
+        })
+    }
+
+    /// Invoices worth less than `min_value` complete without verification.
+    pub fn applies_to(&self, value: U256) -> bool {
+        value >= self.min_value
+    }
+
+    /// Asks every endpoint for the hash of `block` and the invoice balance at
+    /// that hash. The payment is verified when at least `required` endpoints
+    /// agree on both and the agreed balance covers the invoice value.
+    pub async fn verify(&self, invoice: &Invoice, block: u64) -> Result<bool> {
+        let answers = join_all(self.providers.iter().map(|(url, provider)| async move {
+            let answer: Result<(B256, U256)> = async {
+                let hash = indexer::block_hash(provider.clone(), block).await?;
+                let balance = invoice
+                    .balance(provider.clone(), BlockId::hash(hash))
+                    .await?;
+                Ok((hash, balance))
+            }
+            .await;
+            (url, answer)
+        }))
+        .await;
+
+        for (url, answer) in &answers {
+            if let Err(report) = answer {
+                error!("Quorum endpoint {url} failed: {report}");
+            }
+        }
+        let tally = tally(
+            answers
+                .iter()
+                .map(|(_, answer)| answer.as_ref().ok().copied()),
+            self.required,
+        );
+        if tally.disagreement {
+            metrics::QUORUM_DISAGREEMENTS.inc();
+            let answers = answers
+                .iter()
+                .filter_map(|(url, answer)| {
+                    let (hash, balance) = answer.as_ref().ok()?;
+                    Some(format!("{url}: {hash} {balance}"))
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            warn!(
+                target: "quorum",
+                "Endpoints disagree on {} at block {block}: {answers}",
+                invoice.address
+            );
+        }
+
+        Ok(tally
+            .agreed
+            .is_some_and(|(_, balance)| balance >= invoice.value))
+    }
+}
+
+/// Votes of the endpoints on a block hash and balance.
+#[derive(Debug, PartialEq)]
+struct Tally {
+    /// Answer given by at least `required` endpoints.
+    agreed: Option<(B256, U256)>,
+    /// Endpoints that answered gave different answers.
+    disagreement: bool,
+}
+
+/// Counts the answers of the endpoints, `None` for an endpoint that could not
+/// be reached. Unreachable endpoints do not vote.
+fn tally(answers: impl IntoIterator<Item = Option<(B256, U256)>>, required: usize) -> Tally {
+    let mut votes: HashMap<(B256, U256), usize> = HashMap::new();
+    for answer in answers.into_iter().flatten() {
+        *votes.entry(answer).or_default() += 1;
+    }
+    Tally {
+        agreed: votes
+            .iter()
+            .find(|(_, count)| **count >= required)
+            .map(|(answer, _)| *answer),
+        disagreement: votes.len() > 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: B256 = B256::repeat_byte(1);
+    const FORK: B256 = B256::repeat_byte(2);
+
+    fn settings(urls: &[&str]) -> RpcSettings {
+        RpcSettings {
+            urls: urls.iter().map(|url| url.to_string()).collect(),
+            timeout: std::time::Duration::from_secs(1),
+            max_retries: 0,
+            retry_delay: std::time::Duration::from_millis(100),
+        }
+    }
+
+    fn answer(hash: B256, balance: u64) -> Option<(B256, U256)> {
+        Some((hash, U256::from(balance)))
+    }
+
+    #[test]
+    fn agrees_when_required_endpoints_give_the_same_answer() {
+        let votes = tally([answer(HASH, 100), answer(HASH, 100), answer(HASH, 90)], 2);
+        assert_eq!(votes.agreed, answer(HASH, 100));
+        assert!(votes.disagreement);
+
+        let votes = tally([answer(HASH, 100); 3], 3);
+        assert_eq!(votes.agreed, answer(HASH, 100));
+        assert!(!votes.disagreement);
+    }
+
+    #[test]
+    fn different_block_hashes_do_not_agree() {
+        let votes = tally([answer(HASH, 100), answer(FORK, 100)], 2);
+        assert_eq!(votes.agreed, None);
+        assert!(votes.disagreement);
+    }
+
+    #[test]
+    fn unreachable_endpoints_do_not_vote() {
+        let votes = tally([answer(HASH, 100), None, None], 2);
+        assert_eq!(votes.agreed, None);
+        assert!(!votes.disagreement);
+
+        let votes = tally([answer(HASH, 100), None, answer(HASH, 100)], 2);
+        assert_eq!(votes.agreed, answer(HASH, 100));
+        assert!(!votes.disagreement);
+    }
+
+    #[test]
+    fn applies_only_from_min_value() {
+        let quorum = Quorum::new(
+            &settings(&["http://a.test", "http://b.test"]),
+            2,
+            U256::from(1_000),
+        )
+        .unwrap();
+        assert!(!quorum.applies_to(U256::from(999)));
+        assert!(quorum.applies_to(U256::from(1_000)));
+    }
+
+    #[test]
+    fn rejects_unreachable_quorums() {
+        let settings = settings(&["http://a.test", "http://b.test"]);
+        assert!(Quorum::new(&settings, 0, U256::ZERO).is_err());
+        assert!(Quorum::new(&settings, 3, U256::ZERO).is_err());
+    }
+}
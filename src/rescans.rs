@@ -0,0 +1,101 @@
+use std::time::SystemTime;
+
+#[derive(Clone)]
+pub enum RescanStatus {
+    Running,
+    Done,
+}
+
+impl RescanStatus {
+    pub fn to_int(&self) -> u32 {
+        match self {
+            Self::Running => 0,
+            Self::Done => 1,
+        }
+    }
+
+    pub fn from_int(data: u32) -> Self {
+        match data {
+            0 => Self::Running,
+            1 => Self::Done,
+            _ => Self::Done,
+        }
+    }
+}
+
+/// Rescan of a block range for payments to known invoices, processed in
+/// chunks so that it resumes where it stopped.
+pub struct Rescan {
+    pub id: i32,
+    pub from_block: u64,
+    pub to_block: u64,
+    /// First block not scanned yet.
+    pub next_block: u64,
+    pub status: RescanStatus,
+    pub missed_payments: u32,
+    pub repaired_invoices: u32,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl Rescan {
+    /// Records a scanned chunk ending at `last_block`.
+    pub fn advance(&mut self, last_block: u64, missed_payments: u32, repaired_invoices: u32) {
+        self.next_block = last_block + 1;
+        self.missed_payments += missed_payments;
+        self.repaired_invoices += repaired_invoices;
+        if self.next_block > self.to_block {
+            self.status = RescanStatus::Done;
+        }
+        self.updated_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rescan(from_block: u64, to_block: u64) -> Rescan {
+        Rescan {
+            id: 1,
+            from_block,
+            to_block,
+            next_block: from_block,
+            status: RescanStatus::Running,
+            missed_payments: 0,
+            repaired_invoices: 0,
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn advances_chunk_by_chunk_until_done() {
+        let mut rescan = rescan(100, 250);
+
+        rescan.advance(199, 2, 1);
+        assert_eq!(rescan.next_block, 200);
+        assert!(matches!(rescan.status, RescanStatus::Running));
+
+        rescan.advance(249, 0, 0);
+        assert_eq!(rescan.next_block, 250);
+        assert!(matches!(rescan.status, RescanStatus::Running));
+
+        rescan.advance(250, 3, 2);
+        assert_eq!(rescan.next_block, 251);
+        assert!(matches!(rescan.status, RescanStatus::Done));
+        assert_eq!(rescan.missed_payments, 5);
+        assert_eq!(rescan.repaired_invoices, 3);
+        assert!(rescan.updated_at > 0);
+    }
+
+    #[test]
+    fn single_block_rescan_is_done_after_one_chunk() {
+        let mut rescan = rescan(100, 100);
+        rescan.advance(100, 0, 0);
+        assert!(matches!(rescan.status, RescanStatus::Done));
+    }
+}
@@ -0,0 +1,323 @@
+use alloy::providers::RootProvider;
+use alloy::rpc::client::RpcClient;
+use alloy::rpc::json_rpc::{RequestPacket, ResponsePacket, SerializedRequest};
+use alloy::transports::http::{Http, ReqwestTransport};
+use alloy::transports::{TransportError, TransportErrorKind, TransportFut};
+use eyre::Result;
+use log::error;
+use rand::Rng;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower::Service;
+
+/// Consecutive failures after which an endpoint is skipped.
+const FAILURE_THRESHOLD: u32 = 3;
+/// Time a failing endpoint is skipped before it is tried again.
+const OPEN_DURATION: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+pub type FailoverProvider = RootProvider<FailoverTransport>;
+
+#[derive(Clone)]
+pub struct RpcSettings {
+    /// Endpoints in order of preference.
+    pub urls: Vec<String>,
+    /// Timeout of a single call to one endpoint.
+    pub timeout: Duration,
+    /// Retries after the first attempt, each on the healthiest endpoint.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on every next one.
+    pub retry_delay: Duration,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RpcError {
+    #[error("RPC call to {url} timed out after {timeout:?}")]
+    Timeout { url: String, timeout: Duration },
+    #[error("RPC call failed after {attempts} attempts: {source}")]
+    Exhausted {
+        attempts: u32,
+        #[source]
+        source: TransportError,
+    },
+}
+
+struct Endpoint {
+    transport: ReqwestTransport,
+    health: Mutex<Health>,
+}
+
+#[derive(Default)]
+struct Health {
+    consecutive_failures: u32,
+    /// The endpoint is skipped until then.
+    open_until: Option<Instant>,
+}
+
+impl Endpoint {
+    fn is_available(&self, now: Instant) -> bool {
+        self.health
+            .lock()
+            .unwrap()
+            .open_until
+            .is_none_or(|open_until| now >= open_until)
+    }
+
+    fn open_until(&self) -> Option<Instant> {
+        self.health.lock().unwrap().open_until
+    }
+
+    fn succeeded(&self) {
+        *self.health.lock().unwrap() = Health::default();
+    }
+
+    fn failed(&self) {
+        let mut health = self.health.lock().unwrap();
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= FAILURE_THRESHOLD {
+            error!(
+                "RPC endpoint {} failed {} times in a row, skipping it for {}s",
+                self.transport.url(),
+                health.consecutive_failures,
+                OPEN_DURATION.as_secs()
+            );
+            health.open_until = Some(Instant::now() + OPEN_DURATION);
+        }
+    }
+}
+
+/// HTTP transport over several RPC endpoints. Each call goes to the first
+/// available endpoint and is retried with jittered exponential backoff on
+/// timeouts and transport errors. An endpoint that fails `FAILURE_THRESHOLD`
+/// times in a row is skipped for `OPEN_DURATION`.
+#[derive(Clone)]
+pub struct FailoverTransport {
+    endpoints: Arc<Vec<Endpoint>>,
+    timeout: Duration,
+    max_retries: u32,
+    retry_delay: Duration,
+}
+
+impl FailoverTransport {
+    pub fn new(settings: RpcSettings) -> Result<Self> {
+        let endpoints = settings
+            .urls
+            .iter()
+            .map(|url| {
+                Ok(Endpoint {
+                    transport: Http::new(url.parse()?),
+                    health: Mutex::new(Health::default()),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if endpoints.is_empty() {
+            return Err(eyre::eyre!("At least one RPC URL is required"));
+        }
+        Ok(Self {
+            endpoints: Arc::new(endpoints),
+            timeout: settings.timeout,
+            max_retries: settings.max_retries,
+            retry_delay: settings.retry_delay,
+        })
+    }
+
+    pub fn into_provider(self) -> FailoverProvider {
+        RootProvider::new(RpcClient::new(self, false))
+    }
+
+    /// Index of the first available endpoint, or of the one that becomes
+    /// available soonest when all of them are failing. A retry skips the
+    /// endpoint whose call just `failed`, unless it is the only one.
+    fn select_endpoint(&self, now: Instant, failed: Option<usize>) -> usize {
+        let candidates = || {
+            self.endpoints
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| self.endpoints.len() == 1 || Some(*index) != failed)
+        };
+        candidates()
+            .find(|(_, endpoint)| endpoint.is_available(now))
+            .or_else(|| candidates().min_by_key(|(_, endpoint)| endpoint.open_until()))
+            .map(|(index, _)| index)
+            .expect("endpoints are not empty")
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .retry_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_RETRY_DELAY);
+        // Up to 50% jitter so that retries of concurrent calls spread out.
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    async fn send(self, request: RequestPacket) -> Result<ResponsePacket, TransportError> {
+        // A broadcast that timed out may have reached the node, and sending it
+        // again would fail as already known.
+        let max_retries = if is_broadcast(&request) {
+            0
+        } else {
+            self.max_retries
+        };
+        let mut attempt = 0;
+        let mut failed = None;
+        loop {
+            let index = self.select_endpoint(Instant::now(), failed);
+            let endpoint = &self.endpoints[index];
+            let mut transport = endpoint.transport.clone();
+            let error =
+                match tokio::time::timeout(self.timeout, transport.call(request.clone())).await {
+                    Ok(Ok(response)) => {
+                        endpoint.succeeded();
+                        return Ok(response);
+                    }
+                    Ok(Err(error)) => error,
+                    Err(_) => TransportErrorKind::custom(RpcError::Timeout {
+                        url: endpoint.transport.url().to_string(),
+                        timeout: self.timeout,
+                    }),
+                };
+            endpoint.failed();
+            failed = Some(index);
+
+            if attempt >= max_retries {
+                return Err(TransportErrorKind::custom(RpcError::Exhausted {
+                    attempts: attempt + 1,
+                    source: error,
+                }));
+            }
+            tokio::time::sleep(self.backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+}
+
+fn is_broadcast(request: &RequestPacket) -> bool {
+    let is_send = |request: &SerializedRequest| request.method() == "eth_sendRawTransaction";
+    match request {
+        RequestPacket::Single(request) => is_send(request),
+        RequestPacket::Batch(requests) => requests.iter().any(is_send),
+    }
+}
+
+impl Service<RequestPacket> for FailoverTransport {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        Box::pin(self.clone().send(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::rpc::json_rpc::{Id, Request};
+
+    fn transport(urls: &[&str]) -> FailoverTransport {
+        FailoverTransport::new(RpcSettings {
+            urls: urls.iter().map(|url| url.to_string()).collect(),
+            timeout: Duration::from_secs(1),
+            max_retries: 3,
+            retry_delay: Duration::from_millis(100),
+        })
+        .unwrap()
+    }
+
+    fn fail(endpoint: &Endpoint, times: u32) {
+        for _ in 0..times {
+            endpoint.failed();
+        }
+    }
+
+    fn request(method: &'static str) -> SerializedRequest {
+        Request::new(method, Id::Number(1), ()).serialize().unwrap()
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter_up_to_the_cap() {
+        let transport = transport(&["http://a.test"]);
+        for _ in 0..100 {
+            let first = transport.backoff(0);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let third = transport.backoff(2);
+            assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+            let capped = transport.backoff(20);
+            assert!(capped >= MAX_RETRY_DELAY / 2 && capped <= MAX_RETRY_DELAY);
+        }
+    }
+
+    #[test]
+    fn circuit_opens_after_consecutive_failures() {
+        let transport = transport(&["http://a.test", "http://b.test"]);
+        let now = Instant::now();
+        fail(&transport.endpoints[0], FAILURE_THRESHOLD - 1);
+        assert_eq!(transport.select_endpoint(now, None), 0);
+
+        fail(&transport.endpoints[0], 1);
+        assert!(!transport.endpoints[0].is_available(Instant::now()));
+        assert_eq!(transport.select_endpoint(Instant::now(), None), 1);
+    }
+
+    #[test]
+    fn success_resets_the_failure_count() {
+        let transport = transport(&["http://a.test", "http://b.test"]);
+        fail(&transport.endpoints[0], FAILURE_THRESHOLD - 1);
+        transport.endpoints[0].succeeded();
+        fail(&transport.endpoints[0], FAILURE_THRESHOLD - 1);
+        assert_eq!(transport.select_endpoint(Instant::now(), None), 0);
+    }
+
+    #[test]
+    fn open_circuit_is_half_open_after_the_open_duration() {
+        let transport = transport(&["http://a.test", "http://b.test"]);
+        fail(&transport.endpoints[0], FAILURE_THRESHOLD);
+        let reopened = Instant::now() + OPEN_DURATION;
+        assert_eq!(transport.select_endpoint(reopened, None), 0);
+
+        // A single failure while half open skips the endpoint again.
+        fail(&transport.endpoints[0], 1);
+        assert!(!transport.endpoints[0].is_available(Instant::now()));
+    }
+
+    #[test]
+    fn falls_back_to_the_endpoint_reopening_soonest() {
+        let transport = transport(&["http://a.test", "http://b.test"]);
+        fail(&transport.endpoints[1], FAILURE_THRESHOLD);
+        fail(&transport.endpoints[0], FAILURE_THRESHOLD);
+        assert_eq!(transport.select_endpoint(Instant::now(), None), 1);
+    }
+
+    #[test]
+    fn retries_skip_the_endpoint_that_failed() {
+        let transport = transport(&["http://a.test", "http://b.test", "http://c.test"]);
+        let now = Instant::now();
+        assert_eq!(transport.select_endpoint(now, Some(0)), 1);
+        assert_eq!(transport.select_endpoint(now, Some(1)), 0);
+
+        fail(&transport.endpoints[1], FAILURE_THRESHOLD);
+        assert_eq!(transport.select_endpoint(now, Some(0)), 2);
+
+        let single = self::transport(&["http://a.test"]);
+        assert_eq!(single.select_endpoint(now, Some(0)), 0);
+    }
+
+    #[test]
+    fn only_raw_transactions_are_broadcasts() {
+        let send = request("eth_sendRawTransaction");
+        let balance = request("eth_getBalance");
+        assert!(is_broadcast(&RequestPacket::Single(send.clone())));
+        assert!(!is_broadcast(&RequestPacket::Single(balance.clone())));
+        assert!(is_broadcast(&RequestPacket::Batch(vec![
+            balance.clone(),
+            send
+        ])));
+        assert!(!is_broadcast(&RequestPacket::Batch(vec![balance])));
+    }
+}
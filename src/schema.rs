@@ -1,15 +1,158 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (id) {
+        id -> Int4,
+        name -> Varchar,
+        #[max_length = 64]
+        key_hash -> Bpchar,
+        scopes -> Array<Text>,
+        created_at -> Int8,
+        revoked_at -> Nullable<Int8>,
+        merchant_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    indexed_blocks (number) {
+        number -> Int8,
+        #[max_length = 66]
+        hash -> Bpchar,
+    }
+}
+
+diesel::table! {
+    indexer_cursor (id) {
+        id -> Int4,
+        last_scanned_block -> Int8,
+    }
+}
+
 diesel::table! {
     invoice (address) {
         #[max_length = 42]
         address -> Bpchar,
         #[max_length = 42]
         receiver -> Bpchar,
-        mnemonic -> Varchar,
+        mnemonic -> Nullable<Varchar>,
         state -> Int4,
-        value -> Float8,
+        value -> Numeric,
         lifetime -> Int4,
         complete_action -> Int4,
+        #[max_length = 42]
+        token -> Nullable<Bpchar>,
+        decimals -> Int4,
+        top_up_cost -> Numeric,
+        derivation_index -> Nullable<Int4>,
+        confirmations -> Int4,
+        scanned_block -> Int8,
+        #[max_length = 66]
+        pending_tx_hash -> Nullable<Bpchar>,
+        pending_amount -> Nullable<Numeric>,
+        merchant_id -> Nullable<Int4>,
+        webhook_url -> Nullable<Varchar>,
+        fiat_amount -> Nullable<Numeric>,
+        fiat_currency -> Nullable<Varchar>,
+        exchange_rate -> Nullable<Numeric>,
+        rate_timestamp -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    merchants (id) {
+        id -> Int4,
+        name -> Varchar,
+        #[max_length = 42]
+        receiver -> Nullable<Bpchar>,
+        lifetime -> Nullable<Int4>,
+        complete_action -> Nullable<Int4>,
+        webhook_url -> Nullable<Varchar>,
+        created_at -> Int8,
+    }
+}
+
+diesel::table! {
+    payments (id) {
+        id -> Int4,
+        #[max_length = 42]
+        invoice_address -> Bpchar,
+        #[max_length = 66]
+        tx_hash -> Bpchar,
+        block_number -> Int8,
+        #[max_length = 42]
+        sender -> Bpchar,
+        amount -> Numeric,
+        log_index -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    sweeps (id) {
+        id -> Int4,
+        #[max_length = 42]
+        invoice_address -> Bpchar,
+        #[max_length = 66]
+        tx_hash -> Nullable<Bpchar>,
+        nonce -> Nullable<Int8>,
+        max_fee_per_gas -> Nullable<Numeric>,
+        max_priority_fee_per_gas -> Nullable<Numeric>,
+        status -> Int4,
+        attempts -> Int4,
+        last_error -> Nullable<Varchar>,
+        next_attempt_at -> Int8,
+        updated_at -> Int8,
+        submitted_at -> Int8,
+        cancel_requested -> Bool,
+        deadline -> Nullable<Int8>,
+        alerted -> Bool,
+        #[max_length = 66]
+        top_up_tx_hash -> Nullable<Bpchar>,
+    }
+}
+
+diesel::table! {
+    rescans (id) {
+        id -> Int4,
+        from_block -> Int8,
+        to_block -> Int8,
+        next_block -> Int8,
+        status -> Int4,
+        missed_payments -> Int4,
+        repaired_invoices -> Int4,
+        created_at -> Int8,
+        updated_at -> Int8,
+    }
+}
+
+diesel::table! {
+    sweep_transactions (id) {
+        id -> Int4,
+        #[max_length = 42]
+        invoice_address -> Bpchar,
+        #[max_length = 66]
+        tx_hash -> Bpchar,
+        nonce -> Int8,
+        max_fee_per_gas -> Numeric,
+        max_priority_fee_per_gas -> Numeric,
+        kind -> Int4,
+        created_at -> Int8,
     }
 }
+
+diesel::joinable!(api_keys -> merchants (merchant_id));
+diesel::joinable!(invoice -> merchants (merchant_id));
+diesel::joinable!(payments -> invoice (invoice_address));
+diesel::joinable!(sweep_transactions -> invoice (invoice_address));
+diesel::joinable!(sweeps -> invoice (invoice_address));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    indexed_blocks,
+    indexer_cursor,
+    invoice,
+    merchants,
+    payments,
+    rescans,
+    sweep_transactions,
+    sweeps,
+);
@@ -0,0 +1,330 @@
+use crate::fees::Urgency;
+use alloy::primitives::{TxHash, U256};
+use std::time::{Duration, SystemTime};
+
+/// Sweeps are given up after this many broadcast attempts.
+pub const MAX_SWEEP_ATTEMPTS: u32 = 10;
+const SWEEP_RETRY_DELAY: Duration = Duration::from_secs(60);
+const MAX_SWEEP_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+#[derive(Clone)]
+pub enum SweepStatus {
+    Pending,
+    Confirmed,
+    Failed,
+    Cancelled,
+    Deferred,
+}
+
+impl SweepStatus {
+    pub fn to_int(&self) -> u32 {
+        match self {
+            Self::Pending => 0,
+            Self::Confirmed => 1,
+            Self::Failed => 2,
+            Self::Cancelled => 3,
+            Self::Deferred => 4,
+        }
+    }
+
+    pub fn from_int(data: u32) -> Self {
+        match data {
+            0 => Self::Pending,
+            1 => Self::Confirmed,
+            2 => Self::Failed,
+            3 => Self::Cancelled,
+            4 => Self::Deferred,
+            _ => Self::Failed,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum SweepTransactionKind {
+    Sweep,
+    Replacement,
+    Cancel,
+}
+
+impl SweepTransactionKind {
+    pub fn to_int(&self) -> u32 {
+        match self {
+            Self::Sweep => 0,
+            Self::Replacement => 1,
+            Self::Cancel => 2,
+        }
+    }
+
+    pub fn from_int(data: u32) -> Self {
+        match data {
+            0 => Self::Sweep,
+            1 => Self::Replacement,
+            2 => Self::Cancel,
+            _ => Self::Sweep,
+        }
+    }
+}
+
+pub struct SweepSettings {
+    /// Maximum total gas cost of a sweep in wei.
+    pub max_allowed_gas: u128,
+    pub max_priority_fee: u128,
+    /// Time a sweep may stay pending before it is replaced with higher fees.
+    pub stuck_timeout: Duration,
+    pub urgency: Urgency,
+    /// Time a sweep may stay deferred before an operator is alerted.
+    pub defer_deadline: Duration,
+}
+
+/// The sweep would cost more gas than `max_allowed_gas` at current fees.
+#[derive(thiserror::Error, Debug)]
+#[error("Max gas cost {cost} is bigger than maximum gas {max_allowed_gas}")]
+pub struct SweepTooExpensive {
+    pub cost: U256,
+    pub max_allowed_gas: u128,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SweepFees {
+    pub max_fee_per_gas: u128,
+    pub max_priority_fee_per_gas: u128,
+}
+
+impl SweepFees {
+    /// Fees for a replacement transaction. Nodes only accept a replacement
+    /// that raises both fees by at least 10%, so they are raised by 12.5%, or
+    /// to the `current` fees if those are higher.
+    pub fn bumped(&self, current: SweepFees) -> Self {
+        let bump = |fee: u128| fee.saturating_add(fee.div_ceil(8));
+        Self {
+            max_fee_per_gas: bump(self.max_fee_per_gas).max(current.max_fee_per_gas),
+            max_priority_fee_per_gas: bump(self.max_priority_fee_per_gas)
+                .max(current.max_priority_fee_per_gas),
+        }
+    }
+}
+
+/// Broadcast sweep transaction.
+#[derive(Clone)]
+pub struct SweepTransaction {
+    pub tx_hash: TxHash,
+    pub nonce: u64,
+    pub fees: SweepFees,
+    pub kind: SweepTransactionKind,
+}
+
+/// Transfer of invoice funds to the receiver, tracked until it is confirmed.
+#[derive(Clone)]
+pub struct Sweep {
+    pub invoice_address: String,
+    pub tx_hash: Option<String>,
+    pub nonce: Option<u64>,
+    pub max_fee_per_gas: Option<u128>,
+    pub max_priority_fee_per_gas: Option<u128>,
+    pub status: SweepStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// Unix time after which a failed sweep may be retried.
+    pub next_attempt_at: u64,
+    pub updated_at: u64,
+    /// Unix time the latest transaction was broadcast.
+    pub submitted_at: u64,
+    pub cancel_requested: bool,
+    /// Unix time after which a deferred sweep alerts an operator.
+    pub deadline: Option<u64>,
+    pub alerted: bool,
+    /// Funding transfer of a token invoice that has to be mined before the
+    /// sweep is broadcast.
+    pub top_up_tx_hash: Option<String>,
+}
+
+impl Sweep {
+    pub fn new(invoice_address: String) -> Self {
+        Self {
+            invoice_address,
+            tx_hash: None,
+            nonce: None,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            status: SweepStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            next_attempt_at: 0,
+            updated_at: now(),
+            submitted_at: 0,
+            cancel_requested: false,
+            deadline: None,
+            alerted: false,
+            top_up_tx_hash: None,
+        }
+    }
+
+    pub fn fees(&self) -> Option<SweepFees> {
+        Some(SweepFees {
+            max_fee_per_gas: self.max_fee_per_gas?,
+            max_priority_fee_per_gas: self.max_priority_fee_per_gas?,
+        })
+    }
+
+    pub fn is_stuck(&self, stuck_timeout: Duration) -> bool {
+        now() >= self.submitted_at + stuck_timeout.as_secs()
+    }
+
+    pub fn submitted(&mut self, transaction: &SweepTransaction) {
+        self.tx_hash = Some(transaction.tx_hash.to_string());
+        self.nonce = Some(transaction.nonce);
+        self.max_fee_per_gas = Some(transaction.fees.max_fee_per_gas);
+        self.max_priority_fee_per_gas = Some(transaction.fees.max_priority_fee_per_gas);
+        self.status = SweepStatus::Pending;
+        self.last_error = None;
+        self.updated_at = now();
+        self.submitted_at = self.updated_at;
+    }
+
+    pub fn cancelled(&mut self) {
+        self.status = SweepStatus::Cancelled;
+        self.updated_at = now();
+    }
+
+    pub fn confirmed(&mut self) {
+        self.status = SweepStatus::Confirmed;
+        self.updated_at = now();
+    }
+
+    /// Marks the sweep failed and schedules the next attempt with
+    /// exponential backoff. Returns true when it was the last attempt, so the
+    /// operator is alerted once the sweep is given up.
+    pub fn failed(&mut self, error: String) -> bool {
+        let delay = SWEEP_RETRY_DELAY
+            .saturating_mul(2u32.saturating_pow(self.attempts.saturating_sub(1)))
+            .min(MAX_SWEEP_RETRY_DELAY);
+        self.status = SweepStatus::Failed;
+        self.last_error = Some(error);
+        self.updated_at = now();
+        self.next_attempt_at = self.updated_at + delay.as_secs();
+        self.attempts >= MAX_SWEEP_ATTEMPTS
+    }
+
+    /// Marks the sweep deferred until fees drop. Returns true once the
+    /// deadline has passed and the operator has not been alerted yet.
+    pub fn deferred(&mut self, error: String, defer_deadline: Duration) -> bool {
+        self.status = SweepStatus::Deferred;
+        self.last_error = Some(error);
+        self.updated_at = now();
+        let deadline = *self
+            .deadline
+            .get_or_insert(self.updated_at + defer_deadline.as_secs());
+
+        let alert = !self.alerted && self.updated_at >= deadline;
+        self.alerted |= alert;
+        alert
+    }
+
+    pub fn can_retry(&self) -> bool {
+        !matches!(self.status, SweepStatus::Cancelled)
+            && self.attempts < MAX_SWEEP_ATTEMPTS
+            && now() >= self.next_attempt_at
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed_after(attempts: u32) -> Sweep {
+        let mut sweep = Sweep::new("0x0".to_string());
+        sweep.attempts = attempts;
+        sweep.failed("error".to_string());
+        sweep
+    }
+
+    #[test]
+    fn failed_sweeps_back_off_exponentially() {
+        for (attempts, delay) in [(0, 60), (1, 60), (2, 120), (3, 240), (6, 1920)] {
+            let sweep = failed_after(attempts);
+            assert_eq!(sweep.next_attempt_at - sweep.updated_at, delay);
+            assert!(matches!(sweep.status, SweepStatus::Failed));
+            assert_eq!(sweep.last_error.as_deref(), Some("error"));
+        }
+    }
+
+    #[test]
+    fn only_the_last_failed_attempt_alerts() {
+        let mut sweep = Sweep::new("0x0".to_string());
+        for attempts in 1..MAX_SWEEP_ATTEMPTS {
+            sweep.attempts = attempts;
+            assert!(!sweep.failed("error".to_string()));
+        }
+        sweep.attempts = MAX_SWEEP_ATTEMPTS;
+        assert!(sweep.failed("error".to_string()));
+        assert!(!sweep.can_retry());
+    }
+
+    #[test]
+    fn failed_sweep_backoff_is_capped() {
+        for attempts in [7, 9, MAX_SWEEP_ATTEMPTS, u32::MAX] {
+            let sweep = failed_after(attempts);
+            assert_eq!(sweep.next_attempt_at - sweep.updated_at, 3600);
+        }
+    }
+
+    #[test]
+    fn failed_sweep_waits_before_retrying() {
+        let mut sweep = failed_after(1);
+        assert!(!sweep.can_retry());
+
+        sweep.next_attempt_at = now();
+        assert!(sweep.can_retry());
+
+        sweep.attempts = MAX_SWEEP_ATTEMPTS;
+        assert!(!sweep.can_retry());
+    }
+
+    #[test]
+    fn bumped_fees_rise_by_an_eighth() {
+        let fees = SweepFees {
+            max_fee_per_gas: 80,
+            max_priority_fee_per_gas: 9,
+        };
+        let current = SweepFees {
+            max_fee_per_gas: 1,
+            max_priority_fee_per_gas: 1,
+        };
+        let bumped = fees.bumped(current);
+        assert_eq!(bumped.max_fee_per_gas, 90);
+        assert_eq!(bumped.max_priority_fee_per_gas, 11);
+    }
+
+    #[test]
+    fn bumped_fees_follow_higher_current_fees() {
+        let fees = SweepFees {
+            max_fee_per_gas: 80,
+            max_priority_fee_per_gas: 8,
+        };
+        let current = SweepFees {
+            max_fee_per_gas: 200,
+            max_priority_fee_per_gas: 5,
+        };
+        let bumped = fees.bumped(current);
+        assert_eq!(bumped.max_fee_per_gas, 200);
+        assert_eq!(bumped.max_priority_fee_per_gas, 9);
+    }
+
+    #[test]
+    fn bumped_fees_do_not_overflow() {
+        let fees = SweepFees {
+            max_fee_per_gas: u128::MAX,
+            max_priority_fee_per_gas: u128::MAX,
+        };
+        let bumped = fees.bumped(fees);
+        assert_eq!(bumped.max_fee_per_gas, u128::MAX);
+        assert_eq!(bumped.max_priority_fee_per_gas, u128::MAX);
+    }
+}
@@ -1,5 +1,144 @@
 use alloy::primitives::U256;
+use bigdecimal::BigDecimal;
+use eyre::{eyre, Result};
+use std::str::FromStr;
 
-pub fn wei_to_eth(wei: U256) -> f64 {
-    wei.to::<u128>() as f64 / 1e18
+/// Request input that can not be accepted, answered with 400 Bad Request.
+#[derive(thiserror::Error, Debug)]
+#[error("{0}")]
+pub struct InvalidRequest(pub String);
+
+/// Parses an amount given as `"<decimal number> <unit>"` into base units.
+/// Supported units are `wei`, `gwei` and `ether`, where `ether` stands for
+/// `10^decimals` base units (whole tokens for token invoices). `gwei` is only
+/// accepted for 18 decimal amounts. Fails with [`InvalidRequest`].
+pub fn parse_amount(amount: &str, decimals: u8) -> Result<U256> {
+    parse_base_units(amount, decimals).map_err(|report| InvalidRequest(report.to_string()).into())
+}
+
+fn parse_base_units(amount: &str, decimals: u8) -> Result<U256> {
+    let (number, unit) = amount
+        .trim()
+        .split_once(' ')
+        .ok_or_else(|| eyre!("Amount must be \"<number> <unit>\", got \"{amount}\""))?;
+    let exponent = match unit.trim() {
+        "wei" => 0,
+        "gwei" if decimals == 18 => 9,
+        "gwei" => return Err(eyre!("gwei is only supported for 18 decimal amounts")),
+        "ether" => decimals as usize,
+        unit => return Err(eyre!("Unknown unit {unit}")),
+    };
+
+    let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if (integer.is_empty() && fraction.is_empty())
+        || !integer
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return Err(eyre!("Invalid amount {number}"));
+    }
+    if fraction.len() > exponent {
+        return Err(eyre!("Amount {amount} is more precise than one base unit"));
+    }
+
+    Ok(U256::from_str_radix(
+        &format!("{integer}{fraction:0<exponent$}"),
+        10,
+    )?)
+}
+
+pub fn u256_to_numeric(value: U256) -> BigDecimal {
+    BigDecimal::from_str(&value.to_string()).unwrap()
+}
+
+pub fn numeric_to_u256(value: BigDecimal) -> Result<U256> {
+    let (digits, _) = value.with_scale(0).into_bigint_and_exponent();
+    Ok(U256::from_str(&digits.to_string())?)
+}
+
+/// Confirmations of a transaction included in `block` when `latest_block` is
+/// the chain head. The inclusion block is the first confirmation.
+pub fn confirmations(block: u64, latest_block: u64) -> u64 {
+    (latest_block + 1).saturating_sub(block)
+}
+
+/// Latest block whose transactions have at least `confirmations`
+/// confirmations when `latest_block` is the chain head.
+pub fn confirmed_block(latest_block: u64, confirmations: u64) -> u64 {
+    (latest_block + 1).saturating_sub(confirmations.max(1))
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_amounts_in_all_units() {
+        assert_eq!(parse_amount("15 wei", 18).unwrap(), U256::from(15));
+        assert_eq!(
+            parse_amount("1.5 gwei", 18).unwrap(),
+            U256::from(1_500_000_000u64)
+        );
+        assert_eq!(
+            parse_amount("0.0037 ether", 18).unwrap(),
+            U256::from(3_700_000_000_000_000u64)
+        );
+        assert_eq!(parse_amount("2.5 ether", 6).unwrap(), U256::from(2_500_000));
+        assert_eq!(parse_amount(".5 ether", 6).unwrap(), U256::from(500_000));
+    }
+
+    #[test]
+    fn rejects_invalid_amounts() {
+        assert!(parse_amount("1", 18).is_err());
+        assert!(parse_amount("1 btc", 18).is_err());
+        assert!(parse_amount(". ether", 18).is_err());
+        assert!(parse_amount("-1 ether", 18).is_err());
+        assert!(parse_amount("1.5 wei", 18).is_err());
+        assert!(parse_amount("0.0000001 ether", 6).is_err());
+    }
+
+    #[test]
+    fn amount_errors_are_invalid_requests() {
+        let report = parse_amount("1 gwei", 6).unwrap_err();
+        assert!(report.is::<InvalidRequest>());
+        assert!(parse_amount("1e3 wei", 18)
+            .unwrap_err()
+            .is::<InvalidRequest>());
+    }
+
+    #[test]
+    fn rejects_gwei_for_tokens_without_18_decimals() {
+        assert!(parse_amount("1 gwei", 6).is_err());
+    }
+
+    #[test]
+    fn counts_inclusion_block_as_first_confirmation() {
+        assert_eq!(confirmations(100, 100), 1);
+        assert_eq!(confirmations(100, 101), 2);
+        assert_eq!(confirmations(101, 100), 0);
+        assert_eq!(confirmed_block(100, 0), 100);
+        assert_eq!(confirmed_block(100, 1), 100);
+        assert_eq!(confirmed_block(100, 2), 99);
+        assert_eq!(confirmed_block(1, 5), 0);
+        assert_eq!(confirmations(confirmed_block(100, 12), 100), 12);
+    }
+
+    #[test]
+    fn converts_between_u256_and_numeric() {
+        let value = U256::MAX;
+        assert_eq!(numeric_to_u256(u256_to_numeric(value)).unwrap(), value);
+        assert_eq!(
+            u256_to_numeric(U256::from(1234)),
+            BigDecimal::from_str("1234").unwrap()
+        );
+        assert_eq!(
+            numeric_to_u256(BigDecimal::from_str("1234.000").unwrap()).unwrap(),
+            U256::from(1234)
+        );
+    }
 }